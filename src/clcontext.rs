@@ -1,6 +1,10 @@
 use crate::signals::*;
 use crate::turtle::*;
 use crate::tracker::*;
+use crate::layers::LayerRecorder;
+use crate::viewport::{Viewport, ScaleMode};
+use crate::color::{ModelColorIn, ColorModel, parse_hex_color};
+use crate::ops::SignalOps;
 
 use wlambda;
 use wlambda::vval::VVal;
@@ -18,6 +22,8 @@ pub struct ClContext {
     cur_turtle_cmds: Vec<Turtle>,
     turtle_stack:    Vec<Vec<Turtle>>,
     tracker:         Tracker,
+    viewport:        Viewport,
+    sig_ops:         SignalOps,
 }
 
 impl ClContext {
@@ -31,9 +37,23 @@ impl ClContext {
             cur_turtle_cmds: Vec::new(),
             turtle_stack:    Vec::new(),
             tracker:         Tracker::new(),
+            viewport:        Viewport::new(),
+            sig_ops:         SignalOps::new(),
         }))
     }
 
+    fn exec_signal_ops(&mut self, t: f32) {
+        self.sig_ops.exec(t, self.tracker.tempo(), &mut self.sim.regs);
+    }
+
+    /// Allocates the registers up to `idx`, for turtle commands
+    /// that write into registers.
+    fn alloc_reg(&mut self, idx: usize) {
+        if self.sim.regs.len() <= idx {
+            self.sim.regs.resize(idx + 1, 0.0);
+        }
+    }
+
     fn pack_turtle(&mut self) {
         let t =
             Turtle::Commands(
@@ -68,43 +88,70 @@ macro_rules! getOpIn {
     }
 }
 
-macro_rules! getColorIn {
+/// The highest register index accepted by the turtle commands
+/// that write into registers.
+const MAX_REG_IDX : i64 = 0xFFFF;
+
+macro_rules! getRegIdx {
     ($arg: ident, $o: ident) => {
-        let v = $arg.clone();
-        if !v.is_vec() {
-            return Ok(VVal::err_msg(
-                &format!("Bad color argument '{}'", $arg.s())));
+        let $o = if $arg.i() >= 0 && $arg.i() <= MAX_REG_IDX {
+            $arg.i() as usize
+        } else {
+            return Ok(VVal::err_msg(&format!("Bad register index '{}'", $arg.s())));
+        };
+    }
+}
+
+/// Parses a color argument. Either a vector of 4 `OpIn`s (HSVA),
+/// a vector with a color model name in front (`$[:rgb, r, g, b, a]`,
+/// the alpha can be omitted there) or a hex string like `"#ff8800"`.
+fn vv2colorin(v: &VVal) -> Result<ModelColorIn, String> {
+    let hex = v.s_raw();
+    if hex.starts_with('#') {
+        let c = parse_hex_color(&hex)
+            .ok_or_else(|| format!("Bad hex color '{}'", hex))?;
+        let chans : Vec<OpIn> =
+            c.iter()
+             .map(|x| OpIn::vv2opin(VVal::Flt(*x as f64)).unwrap())
+             .collect();
+        return Ok(ModelColorIn::new(
+            ColorModel::Rgb,
+            chans[0].clone(), chans[1].clone(),
+            chans[2].clone(), chans[3].clone()));
+    }
+
+    if !v.is_vec() {
+        return Err(format!("Bad color argument '{}'", v.s()));
+    }
+
+    let (model, offs) =
+        match ColorModel::from_name(&v.at(0).unwrap_or(VVal::Nul).s_raw()) {
+            Some(m) => (m, 1),
+            None    => (ColorModel::Hsv, 0),
+        };
+
+    let mut chans = Vec::new();
+    for i in 0..4 {
+        let default = if offs > 0 && i == 3 { VVal::Flt(1.0) } else { VVal::Nul };
+        let arg = v.at(offs + i).unwrap_or(default);
+        if let Some(o) = OpIn::vv2opin(arg.clone()) {
+            chans.push(o);
+        } else {
+            return Err(format!("Bad register '{}'", arg.s()));
         }
+    }
 
-        let $o = ColorIn {
-            h: if let Some(o) = OpIn::vv2opin(v.at(0).unwrap_or(VVal::Nul)) {
-                o
-            } else {
-                return Ok(VVal::err_msg(
-                    &format!("Bad register '{}'",
-                             v.at(0).unwrap_or(VVal::Nul).s())));
-            },
-            s: if let Some(o) = OpIn::vv2opin(v.at(1).unwrap_or(VVal::Nul)) {
-                o
-            } else {
-                return Ok(VVal::err_msg(
-                    &format!("Bad register '{}'",
-                             v.at(1).unwrap_or(VVal::Nul).s())));
-            },
-            v: if let Some(o) = OpIn::vv2opin(v.at(2).unwrap_or(VVal::Nul)) {
-                o
-            } else {
-                return Ok(VVal::err_msg(
-                    &format!("Bad register '{}'",
-                             v.at(2).unwrap_or(VVal::Nul).s())));
-            },
-            a: if let Some(o) = OpIn::vv2opin(v.at(3).unwrap_or(VVal::Nul)) {
-                o
-            } else {
-                return Ok(VVal::err_msg(
-                    &format!("Bad register '{}'",
-                             v.at(3).unwrap_or(VVal::Nul).s())));
-            },
+    Ok(ModelColorIn::new(
+        model,
+        chans[0].clone(), chans[1].clone(),
+        chans[2].clone(), chans[3].clone()))
+}
+
+macro_rules! getColorIn {
+    ($arg: ident, $o: ident) => {
+        let $o = match vv2colorin(&$arg) {
+            Ok(c)    => c,
+            Err(msg) => return Ok(VVal::err_msg(&msg)),
         };
     }
 }
@@ -160,6 +207,76 @@ impl WLambdaCtx {
                             Err(e) => return Err(e),
                         }
                     },
+                    "clip" => {
+                        getOpIn!(a1, w);
+                        getOpIn!(a2, h);
+
+                        env.with_user_do(|clx: &mut ClContext|
+                            clx.push_turtle());
+                        match a3.call_no_args(env) {
+                            Ok(_v) => {
+                                env.with_user_do(|clx: &mut ClContext| {
+                                    let t = Turtle::Clip(w, h, Box::new(clx.pop_turtle()));
+                                    clx.add_turtle(t);
+                                });
+                            },
+                            Err(e) => return Err(e),
+                        }
+                    },
+                    "symmetry" => {
+                        getOpIn!(a1, n);
+                        let mirror  = a2.b();
+                        getRegIdx!(a3, idx_reg);
+
+                        env.with_user_do(|clx: &mut ClContext|
+                            clx.push_turtle());
+                        match a4.call_no_args(env) {
+                            Ok(_v) => {
+                                env.with_user_do(|clx: &mut ClContext| {
+                                    clx.alloc_reg(idx_reg);
+                                    let t = Turtle::Symmetry(
+                                        n, mirror, idx_reg,
+                                        Box::new(clx.pop_turtle()));
+                                    clx.add_turtle(t);
+                                });
+                            },
+                            Err(e) => return Err(e),
+                        }
+                    },
+                    "instances" => {
+                        getOpIn!(a1, count);
+                        getRegIdx!(a2, base_reg);
+
+                        env.with_user_do(|clx: &mut ClContext|
+                            clx.push_turtle());
+                        match a3.call_no_args(env) {
+                            Ok(_v) => {
+                                env.with_user_do(|clx: &mut ClContext| {
+                                    let t = Turtle::Instances(
+                                        count, base_reg,
+                                        Box::new(clx.pop_turtle()));
+                                    clx.add_turtle(t);
+                                });
+                            },
+                            Err(e) => return Err(e),
+                        }
+                    },
+                    "layer" => {
+                        getOpIn!(a1, layer);
+
+                        env.with_user_do(|clx: &mut ClContext|
+                            clx.push_turtle());
+                        match a2.call_no_args(env) {
+                            Ok(_v) => {
+                                env.with_user_do(|clx: &mut ClContext| {
+                                    let t = Turtle::Layer(
+                                        layer, Box::new(clx.pop_turtle()));
+                                    clx.add_turtle(t);
+                                });
+                            },
+                            Err(e) => return Err(e),
+                        }
+                    },
                     "look_dir" => {
                         getOpIn!(a1, x);
                         getOpIn!(a2, y);
@@ -167,6 +284,40 @@ impl WLambdaCtx {
                         env.with_user_do(|clx: &mut ClContext|
                             clx.add_turtle(Turtle::LookDir(x, y)));
                     },
+                    "anchor" => {
+                        let named_anchor =
+                            match &a1.s_raw()[..] {
+                                "center"       => Some((0.5, 0.5)),
+                                "left_top"     => Some((0.0, 0.0)),
+                                "right_top"    => Some((1.0, 0.0)),
+                                "left_bottom"  => Some((0.0, 1.0)),
+                                "right_bottom" => Some((1.0, 1.0)),
+                                _              => None,
+                            };
+
+                        let (a1, a2) =
+                            if let Some((x, y)) = named_anchor {
+                                (VVal::Flt(x), VVal::Flt(y))
+                            } else {
+                                (a1, a2)
+                            };
+                        getOpIn!(a1, x);
+                        getOpIn!(a2, y);
+
+                        env.with_user_do(|clx: &mut ClContext|
+                            clx.add_turtle(Turtle::Anchor(x, y)));
+                    },
+                    "store_state" => {
+                        getRegIdx!(a1, reg_x);
+                        getRegIdx!(a2, reg_y);
+                        getRegIdx!(a3, reg_angle);
+
+                        env.with_user_do(|clx: &mut ClContext| {
+                            clx.alloc_reg(reg_x.max(reg_y).max(reg_angle));
+                            clx.add_turtle(
+                                Turtle::StoreState(reg_x, reg_y, reg_angle));
+                        });
+                    },
                     "rect" => {
                         getOpIn!(a1, w);
                         getOpIn!(a2, h);
@@ -209,6 +360,21 @@ impl WLambdaCtx {
                 getOpIn!(a, op_in);
 
                 env.with_user_do(|clx: &mut ClContext| {
+                    if let Some(ok) =
+                        clx.sig_ops.set_input(op_idx, &input_name, op_in.clone()) {
+
+                        return
+                            if ok {
+                                Ok(VVal::Bol(true))
+                            } else {
+                                Ok(VVal::err_msg(
+                                    &format!(
+                                        "Bad input '{}' for op ({})",
+                                        input_name,
+                                        op_idx)))
+                            };
+                    }
+
                     if clx.set_op_input(op_idx, &input_name, op_in) {
                         Ok(VVal::Bol(true))
                     } else {
@@ -252,20 +418,72 @@ impl WLambdaCtx {
                 }
             }, Some(1), Some(2));
 
+        genv.borrow_mut().add_func(
+            "tempo", |env: &mut Env, argc: usize| {
+                let bpm = env.arg(0).i();
+                let rpb = if argc > 1 { env.arg(1).i() } else { 1 };
+                if bpm <= 0 || rpb <= 0 {
+                    return Ok(VVal::err_msg(
+                        &format!("Bad tempo {} bpm, {} rpb", bpm, rpb)));
+                }
+
+                env.with_user_do(|clx: &mut ClContext|
+                    clx.tracker.set_tempo(bpm as usize, rpb as usize));
+
+                Ok(VVal::Bol(true))
+            }, Some(1), Some(2));
+
+        genv.borrow_mut().add_func(
+            "canvas", |env: &mut Env, argc: usize| {
+                let w = env.arg(0).f() as f32;
+                let h = env.arg(1).f() as f32;
+                if w <= 0.0 || h <= 0.0 {
+                    return Ok(VVal::err_msg(
+                        &format!("Bad canvas size {}x{}", w, h)));
+                }
+
+                let mode =
+                    if argc > 2 {
+                        let mode_name = env.arg(2).s_raw();
+                        if let Some(m) = ScaleMode::from_name(&mode_name) {
+                            m
+                        } else {
+                            return Ok(VVal::err_msg(
+                                &format!("Bad canvas mode '{}'", mode_name)));
+                        }
+                    } else {
+                        ScaleMode::Fit
+                    };
+
+                env.with_user_do(|clx: &mut ClContext| {
+                    clx.viewport = Viewport { width: w, height: h, mode };
+                });
+
+                Ok(VVal::Bol(true))
+            }, Some(2), Some(3));
+
         genv.borrow_mut().add_func(
             "new", |env: &mut Env, _argc: usize| {
                 let idx = env.arg(0).i() as usize;
                 let t   = env.arg(1).s_raw();
+                let cfg = env.arg(2);
 
                 env.with_user_do(|clx: &mut ClContext| {
+                    match clx.sig_ops.new_op(idx, &t, &cfg, &mut clx.sim.regs) {
+                        Ok(Some(i)) => return Ok(VVal::Int(i as i64)),
+                        Ok(None)    => (),
+                        Err(msg)    => return Ok(VVal::err_msg(&msg)),
+                    }
+
                     let o = clx.new_op(idx, &t);
                     if let Some(i) = o {
+                        clx.sig_ops.remove_op(idx);
                         Ok(VVal::Int(i as i64))
                     } else {
                         Ok(VVal::err_msg(&format!("Bad op type '{}'", t)))
                     }
                 })
-            }, Some(2), Some(2));
+            }, Some(2), Some(3));
 
         self.evalctx =
             Some(
@@ -275,8 +493,7 @@ impl WLambdaCtx {
     }
 
     pub fn load_script(&mut self, filename: &str) {
-        self.evalctx.as_mut().unwrap().eval_file(
-            &std::env::args().nth(1).unwrap_or(filename.to_string())).unwrap();
+        self.evalctx.as_mut().unwrap().eval_file(filename).unwrap();
 
         let draw_cb = self.evalctx.as_mut().unwrap().get_global_var("draw");
         if draw_cb.is_none() {
@@ -290,16 +507,35 @@ impl WLambdaCtx {
         self.draw_cb = draw_cb;
     }
 
-    pub fn one_step<T>(&mut self, t: i64, scale_size: f32, p: &mut T) where T: TurtleDrawing {
+    pub fn viewport(&self) -> Viewport {
+        self.clctx.borrow().viewport.clone()
+    }
+
+    /// Executes the script and the turtle program for the time `t`
+    /// in milliseconds. `out_size` is the output size in pixels,
+    /// which is mapped to the canvas defined by the script.
+    pub fn one_step<T>(&mut self, t: i64, out_size: [f32; 2], p: &mut T) where T: TurtleDrawing {
         self.evalctx.as_mut().unwrap().call(
             &self.draw_cb,
             &vec![VVal::Int(t)]).unwrap();
+        // The SignalOps run after the Simulator ops, see `SignalOps`.
         self.clctx.borrow_mut().exec(t as f32);
+        self.clctx.borrow_mut().exec_signal_ops(t as f32);
 
         let t = self.clctx.borrow_mut().cur_turtle_cmds[0].clone();
 
-        let mut ts = TurtleState::new(scale_size, scale_size);
-        t.exec(&mut ts, &mut self.clctx.borrow_mut().sim.regs, p);
+        let vp   = self.viewport();
+        let unit = vp.unit_size(out_size[0], out_size[1]);
+
+        let mut ts = TurtleState::new(unit[0], unit[1]);
+        let mut rec = LayerRecorder::new();
+        t.exec(&mut ts, &mut self.clctx.borrow_mut().sim.regs, &mut rec);
+
+        let clipped = vp.push_letterbox(out_size[0], out_size[1], p);
+        rec.replay(p);
+        if clipped {
+            p.pop_clip();
+        }
     }
 
     pub fn show_debug_registers<T>(&mut self, p: &mut T) where T: RegisterView {
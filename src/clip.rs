@@ -0,0 +1,147 @@
+use crate::turtle::{ShapeRotation, rotate_vec2};
+
+/// A convex polygon, used for restricting the drawing of
+/// the `TurtleDrawing` backends to a (possibly rotated) region.
+/// The points are in clockwise order (in screen coordinates).
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClipPolygon {
+    pub points: Vec<[f32; 2]>,
+}
+
+/// Signed side of `p` relative to the edge `a` -> `b`.
+/// Positive values are on the inner side of a clockwise polygon.
+fn edge_side(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Returns the absolute area of the polygon `points`.
+pub fn polygon_area(points: &[[f32; 2]]) -> f32 {
+    let n = points.len();
+    let mut sum = 0.0;
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        sum += a[0] * b[1] - b[0] * a[1];
+    }
+    (sum / 2.0).abs()
+}
+
+/// Returns the corners of a rotated rectangle with the pivot at `pos`.
+pub fn rect_corners(rot: &ShapeRotation, pos: [f32; 2], size: [f32; 2]) -> Vec<[f32; 2]> {
+    let w     = size[0].abs();
+    let h     = size[1].abs();
+    let offs  = rot.rect_offset([w, h]);
+    let angle = rot.angle();
+
+    [[0.0, 0.0], [w, 0.0], [w, h], [0.0, h]].iter().map(|c| {
+        let r = rotate_vec2([offs[0] + c[0], offs[1] + c[1]], angle);
+        [pos[0] + r[0], pos[1] + r[1]]
+    }).collect()
+}
+
+/// Returns the corners of a line segment with the given thickness.
+pub fn line_corners(from: [f32; 2], to: [f32; 2], thickness: f32) -> Vec<[f32; 2]> {
+    let d   = [to[0] - from[0], to[1] - from[1]];
+    let len = (d[0] * d[0] + d[1] * d[1]).sqrt();
+    if len <= 0.0 { return Vec::new(); }
+
+    let t = thickness / 2.0;
+    let n = [-d[1] / len * t, d[0] / len * t];
+    vec![
+        [from[0] - n[0], from[1] - n[1]],
+        [to[0]   - n[0], to[1]   - n[1]],
+        [to[0]   + n[0], to[1]   + n[1]],
+        [from[0] + n[0], from[1] + n[1]],
+    ]
+}
+
+/// Splits the outline of a rotated rectangle into four
+/// non overlapping quads, one per edge.
+pub fn rect_outline_quads(rot: &ShapeRotation, pos: [f32; 2], size: [f32; 2], thickness: f32) -> Vec<Vec<[f32; 2]>> {
+    let w     = size[0].abs();
+    let h     = size[1].abs();
+    let offs  = rot.rect_offset([w, h]);
+    let angle = rot.angle();
+    let t     = thickness.abs() / 2.0;
+
+    let quad = |x0: f32, y0: f32, x1: f32, y1: f32| {
+        [[x0, y0], [x1, y0], [x1, y1], [x0, y1]].iter().map(|c| {
+            let r = rotate_vec2([offs[0] + c[0], offs[1] + c[1]], angle);
+            [pos[0] + r[0], pos[1] + r[1]]
+        }).collect::<Vec<[f32; 2]>>()
+    };
+
+    vec![
+        quad(-t,    -t,    w + t, t),
+        quad(-t,    h - t, w + t, h + t),
+        quad(-t,    t,     t,     h - t),
+        quad(w - t, t,     w + t, h - t),
+    ]
+}
+
+impl ClipPolygon {
+    pub fn from_rect(rot: &ShapeRotation, pos: [f32; 2], size: [f32; 2]) -> Self {
+        ClipPolygon { points: rect_corners(rot, pos, size) }
+    }
+
+    /// Clips the convex polygon `poly` against this clip region
+    /// (Sutherland-Hodgman).
+    pub fn clip(&self, poly: &[[f32; 2]]) -> Vec<[f32; 2]> {
+        let n = self.points.len();
+        if n < 3 { return Vec::new(); }
+
+        let mut out : Vec<[f32; 2]> = poly.to_vec();
+        for i in 0..n {
+            if out.is_empty() { break; }
+
+            let a = self.points[i];
+            let b = self.points[(i + 1) % n];
+
+            let input = std::mem::replace(&mut out, Vec::new());
+            let mut prev = input[input.len() - 1];
+            let mut prev_side = edge_side(a, b, prev);
+
+            for p in input.iter() {
+                let side = edge_side(a, b, *p);
+
+                if (side >= 0.0) != (prev_side >= 0.0) {
+                    let f = prev_side / (prev_side - side);
+                    out.push([
+                        prev[0] + (p[0] - prev[0]) * f,
+                        prev[1] + (p[1] - prev[1]) * f,
+                    ]);
+                }
+                if side >= 0.0 {
+                    out.push(*p);
+                }
+
+                prev      = *p;
+                prev_side = side;
+            }
+        }
+
+        out
+    }
+
+    /// Returns the intersection of this region with `other`.
+    pub fn intersect(&self, other: &ClipPolygon) -> ClipPolygon {
+        ClipPolygon { points: self.clip(&other.points) }
+    }
+
+    /// Approximated signed distance of `p` to the region border,
+    /// negative values are inside.
+    pub fn distance(&self, p: [f32; 2]) -> f32 {
+        let n = self.points.len();
+        if n < 3 { return std::f32::INFINITY; }
+
+        let mut d = std::f32::NEG_INFINITY;
+        for i in 0..n {
+            let a   = self.points[i];
+            let b   = self.points[(i + 1) % n];
+            let len = ((b[0] - a[0]).powi(2) + (b[1] - a[1]).powi(2)).sqrt();
+            if len <= 0.0 { continue; }
+            d = d.max(-edge_side(a, b, p) / len);
+        }
+        d
+    }
+}
@@ -0,0 +1,135 @@
+use crate::signals::{OpIn, ColorIn};
+use crate::util::clamp01;
+
+/// The color model the four channels of a `ModelColorIn` are
+/// interpreted in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorModel {
+    /// hue, saturation, value, alpha (the `ColorIn` default)
+    Hsv,
+    /// red, green, blue, alpha (0.0 to 1.0)
+    Rgb,
+    /// hue in degrees, saturation, lightness, alpha
+    Hsl,
+    /// OKLab lightness (0.0 to 1.0), a, b (about -0.4 to 0.4), alpha
+    Lab,
+}
+
+impl ColorModel {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "hsv" => Some(ColorModel::Hsv),
+            "rgb" => Some(ColorModel::Rgb),
+            "hsl" => Some(ColorModel::Hsl),
+            "lab" => Some(ColorModel::Lab),
+            _     => None,
+        }
+    }
+}
+
+/// A `ColorIn` together with the color model of its channels.
+/// `calc` converts the channels to RGBA once per evaluation.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModelColorIn {
+    pub model: ColorModel,
+    pub chans: ColorIn,
+}
+
+fn hsl2rgb(h: f32, s: f32, l: f32) -> [f32; 3] {
+    let h = (h % 360.0 + 360.0) % 360.0 / 60.0;
+    let s = clamp01(s);
+    let l = clamp01(l);
+
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) =
+        match h as usize {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+    [r + m, g + m, b + m]
+}
+
+fn linear2srgb(v: f32) -> f32 {
+    let v = clamp01(v);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn oklab2rgb(l: f32, a: f32, b: f32) -> [f32; 3] {
+    let l_ = l + 0.396_337_78 * a + 0.215_803_76 * b;
+    let m_ = l - 0.105_561_35 * a - 0.063_854_17 * b;
+    let s_ = l - 0.089_484_18 * a - 1.291_485_5  * b;
+
+    let l = l_ * l_ * l_;
+    let m = m_ * m_ * m_;
+    let s = s_ * s_ * s_;
+
+    [
+        linear2srgb( 4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s),
+        linear2srgb(-1.268_438   * l + 2.609_757_4 * m - 0.341_319_38 * s),
+        linear2srgb(-0.004_196_086 * l - 0.703_418_6 * m + 1.707_614_7 * s),
+    ]
+}
+
+/// Parses "#rgb", "#rrggbb" or "#rrggbbaa" into RGBA (0.0 to 1.0).
+pub fn parse_hex_color(s: &str) -> Option<[f32; 4]> {
+    if !s.starts_with('#') { return None; }
+    let hex = &s[1..];
+
+    let digits : Vec<u32> =
+        hex.chars().map(|c| c.to_digit(16)).collect::<Option<Vec<u32>>>()?;
+
+    let bytes : Vec<u32> =
+        match digits.len() {
+            3 => digits.iter().map(|d| d * 17).chain(Some(255)).collect(),
+            6 | 8 => digits.chunks(2).map(|d| d[0] * 16 + d[1]).collect(),
+            _ => return None,
+        };
+
+    Some([
+        bytes[0] as f32 / 255.0,
+        bytes[1] as f32 / 255.0,
+        bytes[2] as f32 / 255.0,
+        if bytes.len() > 3 { bytes[3] as f32 / 255.0 } else { 1.0 },
+    ])
+}
+
+impl ModelColorIn {
+    pub fn new(model: ColorModel, h: OpIn, s: OpIn, v: OpIn, a: OpIn) -> Self {
+        ModelColorIn { model, chans: ColorIn { h, s, v, a } }
+    }
+
+    /// Returns the color as RGBA.
+    pub fn calc(&self, regs: &[f32]) -> [f32; 4] {
+        if self.model == ColorModel::Hsv {
+            return self.chans.calc(regs);
+        }
+
+        let c = [
+            self.chans.h.calc(regs) as f32,
+            self.chans.s.calc(regs) as f32,
+            self.chans.v.calc(regs) as f32,
+        ];
+        let a = clamp01(self.chans.a.calc(regs) as f32);
+
+        let rgb =
+            match self.model {
+                ColorModel::Rgb => [clamp01(c[0]), clamp01(c[1]), clamp01(c[2])],
+                ColorModel::Hsl => hsl2rgb(c[0], c[1], c[2]),
+                ColorModel::Lab => oklab2rgb(c[0], c[1], c[2]),
+                ColorModel::Hsv => unreachable!(),
+            };
+
+        [rgb[0], rgb[1], rgb[2], a]
+    }
+}
@@ -0,0 +1,92 @@
+use crate::util::clamp01;
+
+use std::f32::consts::PI;
+
+/// Easing curves, mapping 0.0 to 1.0 onto 0.0 to 1.0.
+/// `elastic` and `bounce` ease out and overshoot or bounce
+/// at the end.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Easing {
+    Linear,
+    SmoothStep,
+    SinIn,
+    SinOut,
+    SinInOut,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    Elastic,
+    Bounce,
+}
+
+fn bounce_out(x: f32) -> f32 {
+    let n = 7.5625;
+    let d = 2.75;
+    if x < 1.0 / d {
+        n * x * x
+    } else if x < 2.0 / d {
+        let x = x - 1.5 / d;
+        n * x * x + 0.75
+    } else if x < 2.5 / d {
+        let x = x - 2.25 / d;
+        n * x * x + 0.9375
+    } else {
+        let x = x - 2.625 / d;
+        n * x * x + 0.984375
+    }
+}
+
+impl Easing {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "linear"    => Some(Easing::Linear),
+            "sstep"     => Some(Easing::SmoothStep),
+            "sin_in"    => Some(Easing::SinIn),
+            "sin_out"   => Some(Easing::SinOut),
+            "sin"       => Some(Easing::SinInOut),
+            "quad_in"   => Some(Easing::QuadIn),
+            "quad_out"  => Some(Easing::QuadOut),
+            "quad"      => Some(Easing::QuadInOut),
+            "cubic_in"  => Some(Easing::CubicIn),
+            "cubic_out" => Some(Easing::CubicOut),
+            "cubic"     => Some(Easing::CubicInOut),
+            "elastic"   => Some(Easing::Elastic),
+            "bounce"    => Some(Easing::Bounce),
+            _           => None,
+        }
+    }
+
+    /// Applies the curve to `x`, which is clamped to 0.0 to 1.0.
+    pub fn ease(&self, x: f32) -> f32 {
+        let x = clamp01(x);
+
+        match self {
+            Easing::Linear     => x,
+            Easing::SmoothStep => x * x * (3.0 - 2.0 * x),
+            Easing::SinIn      => 1.0 - (x * PI / 2.0).cos(),
+            Easing::SinOut     => (x * PI / 2.0).sin(),
+            Easing::SinInOut   => (1.0 - (x * PI).cos()) / 2.0,
+            Easing::QuadIn     => x * x,
+            Easing::QuadOut    => 1.0 - (1.0 - x) * (1.0 - x),
+            Easing::QuadInOut  =>
+                if x < 0.5 { 2.0 * x * x }
+                else { 1.0 - (-2.0 * x + 2.0).powi(2) / 2.0 },
+            Easing::CubicIn    => x * x * x,
+            Easing::CubicOut   => 1.0 - (1.0 - x).powi(3),
+            Easing::CubicInOut =>
+                if x < 0.5 { 4.0 * x * x * x }
+                else { 1.0 - (-2.0 * x + 2.0).powi(3) / 2.0 },
+            Easing::Elastic    =>
+                if x <= 0.0 || x >= 1.0 { x }
+                else {
+                    (2.0 as f32).powf(-10.0 * x)
+                    * ((x * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin()
+                    + 1.0
+                },
+            Easing::Bounce     => bounce_out(x),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::clcontext::WLambdaCtx;
+use crate::softraster::SoftRaster;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub enum FrameOutput {
+    /// Writes numbered PNG files into the given directory.
+    PngDir(String),
+    /// Writes all frames into a single YUV4MPEG2 stream.
+    Y4M(String),
+}
+
+pub struct RenderSettings {
+    pub width:      usize,
+    pub height:     usize,
+    pub fps:        usize,
+    /// start time in milliseconds
+    pub start_ms:   i64,
+    /// end time in milliseconds (exclusive)
+    pub end_ms:     i64,
+    pub output:     FrameOutput,
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        RenderSettings {
+            width:    640,
+            height:   480,
+            fps:      30,
+            start_ms: 0,
+            end_ms:   10000,
+            output:   FrameOutput::PngDir(String::from("frames")),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        if self.end_ms <= self.start_ms || self.fps == 0 { return 0; }
+        ((self.end_ms - self.start_ms) as usize * self.fps + 999) / 1000
+    }
+
+    /// The deterministic time of the frame `frame_idx` in milliseconds.
+    pub fn frame_time(&self, frame_idx: usize) -> i64 {
+        self.start_ms + ((frame_idx * 1000) / self.fps) as i64
+    }
+}
+
+struct Y4MWriter {
+    out: BufWriter<File>,
+}
+
+impl Y4MWriter {
+    fn new(filename: &str, rs: &RenderSettings) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                 rs.width, rs.height, rs.fps)?;
+        Ok(Y4MWriter { out })
+    }
+
+    fn write_frame(&mut self, fb: &SoftRaster) -> std::io::Result<()> {
+        let rgba = fb.to_rgba8();
+        let plane_len = fb.width() * fb.height();
+        let mut planes = vec![0u8; plane_len * 3];
+
+        // BT.601 studio range
+        for (i, px) in rgba.chunks(4).enumerate() {
+            let r = px[0] as f32 / 255.0;
+            let g = px[1] as f32 / 255.0;
+            let b = px[2] as f32 / 255.0;
+            let y  =  16.0 + 65.481 * r + 128.553 * g +  24.966 * b;
+            let cb = 128.0 - 37.797 * r -  74.203 * g + 112.0   * b;
+            let cr = 128.0 + 112.0  * r -  93.786 * g -  18.214 * b;
+            planes[i]                 = y.round()  as u8;
+            planes[plane_len + i]     = cb.round() as u8;
+            planes[plane_len * 2 + i] = cr.round() as u8;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)
+    }
+}
+
+/// Renders the frames from `rs.start_ms` to `rs.end_ms` with a fixed
+/// frame rate, independent of the wall clock.
+pub fn render_frames(wlctx: &mut WLambdaCtx, rs: &RenderSettings) -> std::io::Result<()> {
+    let mut fb = SoftRaster::new(rs.width, rs.height);
+    let out_size = [rs.width as f32, rs.height as f32];
+
+    let mut y4m =
+        match &rs.output {
+            FrameOutput::PngDir(dir) => {
+                std::fs::create_dir_all(dir)?;
+                None
+            },
+            FrameOutput::Y4M(filename) => Some(Y4MWriter::new(filename, rs)?),
+        };
+
+    let frame_count = rs.frame_count();
+    for frame_idx in 0..frame_count {
+        fb.clear([0.0, 0.0, 0.0, 1.0]);
+        wlctx.one_step(rs.frame_time(frame_idx), out_size, &mut fb);
+
+        match &rs.output {
+            FrameOutput::PngDir(dir) => {
+                fb.write_png(&format!("{}/frame_{:06}.png", dir, frame_idx))?;
+            },
+            FrameOutput::Y4M(_) => {
+                y4m.as_mut().unwrap().write_frame(&fb)?;
+            },
+        }
+
+        if frame_idx % rs.fps == 0 {
+            eprintln!("rendered frame {}/{}", frame_idx, frame_count);
+        }
+    }
+
+    if let Some(y4m) = y4m.as_mut() {
+        y4m.out.flush()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use crate::turtle::{TurtleDrawing, ShapeRotation};
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+struct ClipRect {
+    rot:  ShapeRotation,
+    pos:  [f32; 2],
+    size: [f32; 2],
+}
+
+#[derive(Debug, Clone)]
+enum DrawCmd {
+    Line(ShapeRotation, [f32; 2], [f32; 2], f32),
+    RectFill(ShapeRotation, [f32; 2], [f32; 2]),
+    RectOutline(ShapeRotation, [f32; 2], [f32; 2], f32),
+}
+
+#[derive(Debug, Clone)]
+struct LayeredCmd {
+    layer: i64,
+    color: [f32; 4],
+    /// the clip regions that were active when the command was recorded
+    clips: Rc<Vec<ClipRect>>,
+    cmd:   DrawCmd,
+}
+
+/// Records the primitives of a turtle program together with their
+/// layer number. `replay` draws them sorted by layer,
+/// primitives of the same layer are drawn in program order.
+pub struct LayerRecorder {
+    cur_layer: i64,
+    clips:     Rc<Vec<ClipRect>>,
+    cmds:      Vec<LayeredCmd>,
+}
+
+impl LayerRecorder {
+    pub fn new() -> Self {
+        LayerRecorder {
+            cur_layer: 0,
+            clips:     Rc::new(Vec::new()),
+            cmds:      Vec::new(),
+        }
+    }
+
+    fn record(&mut self, color: [f32; 4], cmd: DrawCmd) {
+        self.cmds.push(LayeredCmd {
+            layer: self.cur_layer,
+            color,
+            clips: self.clips.clone(),
+            cmd,
+        });
+    }
+
+    /// Draws all recorded primitives sorted by layer and clears
+    /// the recorder.
+    pub fn replay<T>(&mut self, out: &mut T) where T: TurtleDrawing {
+        let mut cmds = std::mem::replace(&mut self.cmds, Vec::new());
+        cmds.sort_by_key(|c| c.layer);
+
+        let mut cur_clips : Rc<Vec<ClipRect>> = Rc::new(Vec::new());
+        for c in cmds.into_iter() {
+            if !Rc::ptr_eq(&cur_clips, &c.clips) {
+                for _ in cur_clips.iter() {
+                    out.pop_clip();
+                }
+                for cr in c.clips.iter() {
+                    out.push_clip(cr.rot.clone(), cr.pos, cr.size);
+                }
+                cur_clips = c.clips.clone();
+            }
+
+            match c.cmd {
+                DrawCmd::Line(rot, from, to, thickness) => {
+                    out.draw_line(c.color, rot, from, to, thickness);
+                },
+                DrawCmd::RectFill(rot, pos, size) => {
+                    out.draw_rect_fill(c.color, rot, pos, size);
+                },
+                DrawCmd::RectOutline(rot, pos, size, thickness) => {
+                    out.draw_rect_outline(c.color, rot, pos, size, thickness);
+                },
+            }
+        }
+
+        for _ in cur_clips.iter() {
+            out.pop_clip();
+        }
+    }
+}
+
+impl TurtleDrawing for LayerRecorder {
+    fn draw_line(&mut self, color: [f32; 4], rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32) {
+        self.record(color, DrawCmd::Line(rot, from, to, thickness));
+    }
+
+    fn draw_rect_fill(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        self.record(color, DrawCmd::RectFill(rot, pos, size));
+    }
+
+    fn draw_rect_outline(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2], thickness: f32) {
+        self.record(color, DrawCmd::RectOutline(rot, pos, size, thickness));
+    }
+
+    fn push_clip(&mut self, rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let mut clips = (*self.clips).clone();
+        clips.push(ClipRect { rot, pos, size });
+        self.clips = Rc::new(clips);
+    }
+
+    fn pop_clip(&mut self) {
+        let mut clips = (*self.clips).clone();
+        clips.pop();
+        self.clips = Rc::new(clips);
+    }
+
+    fn set_layer(&mut self, layer: i64) -> i64 {
+        std::mem::replace(&mut self.cur_layer, layer)
+    }
+}
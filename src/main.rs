@@ -2,10 +2,22 @@ mod turtle;
 mod signals;
 mod clcontext;
 mod tracker;
+mod softraster;
+mod headless;
+mod clip;
+mod layers;
+mod viewport;
+mod color;
+mod ops;
+mod easing;
+mod noise;
+mod util;
 
 use clcontext::WLambdaCtx;
 use turtle::{TurtleDrawing, ShapeRotation};
 use tracker::{TrackerEditorView, Interpolation};
+use headless::{RenderSettings, FrameOutput};
+use clip::ClipPolygon;
 //use std::time::{Instant};
 
 use ggez::{Context, ContextBuilder, GameResult};
@@ -84,14 +96,50 @@ struct Painter<'a> {
     ctx: &'a mut Context,
     reg_view_font: &'a graphics::Font,
     cur_reg_line: usize,
+    /// The clip regions of `t :clip` rotate with the turtle, which an
+    /// (axis aligned) scissor rectangle can't express, and ggez 0.5 does
+    /// not expose one anyways. So while a region is active, the primitives
+    /// are drawn as polygons that are clipped on the CPU.
+    clip_stack: Vec<ClipPolygon>,
 }
 
 impl<'a> Painter<'a> {
+    /// Draws the convex polygon `poly`, clipped against the current
+    /// clip region.
+    fn draw_polygon_clipped(&mut self, color: [f32; 4], poly: &[[f32; 2]]) {
+        let points =
+            if let Some(clip) = self.clip_stack.last() {
+                clip.clip(poly)
+            } else {
+                poly.to_vec()
+            };
+        // the clipped polygon can collapse, for instance when the shape
+        // only touches the clip border, ggez can't build a mesh for that
+        if points.len() < 3 || clip::polygon_area(&points) < 0.001 { return; }
+
+        let p =
+            graphics::Mesh::new_polygon(
+                self.ctx,
+                graphics::DrawMode::fill(),
+                &points,
+                graphics::Color::from(color)).unwrap();
+        graphics::draw(self.ctx, &p, graphics::DrawParam::default()).unwrap();
+    }
+
     fn draw_rect(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2], filled: bool, thickness: f32) {
-        let rot = match rot {
-            ShapeRotation::Center(a) => a,
-            _ => 0.0,
-        };
+        if !self.clip_stack.is_empty() {
+            if filled {
+                self.draw_polygon_clipped(
+                    color, &clip::rect_corners(&rot, pos, size));
+            } else {
+                for q in clip::rect_outline_quads(&rot, pos, size, thickness) {
+                    self.draw_polygon_clipped(color, &q);
+                }
+            }
+            return;
+        }
+
+        let offs = rot.rect_offset(size);
         let r =
             graphics::Mesh::new_rectangle(
                 self.ctx,
@@ -100,13 +148,13 @@ impl<'a> Painter<'a> {
                 } else {
                     graphics::DrawMode::stroke(thickness)
                 },
-                graphics::Rect::new(-size[0] / 2.0, -size[1] / 2.0, size[0], size[1]),
+                graphics::Rect::new(offs[0], offs[1], size[0], size[1]),
                 graphics::Color::from(color)).unwrap();
         graphics::draw(
             self.ctx,
             &r,
             ([pos[0], pos[1]],
-             rot,
+             rot.angle(),
              [0.0, 0.0],
              graphics::WHITE)).unwrap();
     }
@@ -161,7 +209,14 @@ impl<'a> TrackerEditorView for Painter<'a> {
 }
 
 impl<'a> TurtleDrawing for Painter<'a> {
-    fn draw_line(&mut self, color: [f32; 4], _rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32) {
+    fn draw_line(&mut self, color: [f32; 4], rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32) {
+        let (from, to) = rot.rotate_line(from, to, thickness);
+        if !self.clip_stack.is_empty() {
+            self.draw_polygon_clipped(
+                color, &clip::line_corners(from, to, thickness));
+            return;
+        }
+
         let line =
             graphics::Mesh::new_line(
                 self.ctx,
@@ -181,6 +236,21 @@ impl<'a> TurtleDrawing for Painter<'a> {
     fn draw_rect_fill(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
         self.draw_rect(color, rot, pos, size, true, 0.0);
     }
+
+    fn push_clip(&mut self, rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let clip = ClipPolygon::from_rect(&rot, pos, size);
+        let clip =
+            if let Some(cur) = self.clip_stack.last() {
+                cur.intersect(&clip)
+            } else {
+                clip
+            };
+        self.clip_stack.push(clip);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clip_stack.pop();
+    }
 }
 
 struct WCtrDemEngine {
@@ -190,10 +260,10 @@ struct WCtrDemEngine {
 }
 
 impl WCtrDemEngine {
-    pub fn new(ctx: &mut Context) -> WCtrDemEngine {
+    pub fn new(ctx: &mut Context, script: &str) -> WCtrDemEngine {
         let mut wlctx = WLambdaCtx::new();
         wlctx.init();
-        wlctx.load_script("in.wl");
+        wlctx.load_script(script);
         let font = graphics::Font::new(ctx, "/DejaVuSansMono.ttf").unwrap();
         WCtrDemEngine {
             wlctx,
@@ -225,10 +295,14 @@ impl EventHandler for WCtrDemEngine {
         graphics::apply_transformations(ctx)?;
 
         let now_time = ggez::timer::time_since_start(ctx).as_millis();
-        let scale_size = 300.0;
         {
-            let mut p = Painter { ctx, cur_reg_line: 0, reg_view_font: &self.debug_font };
-            self.wlctx.one_step(now_time as i64, scale_size, &mut p);
+            let mut p = Painter {
+                ctx,
+                cur_reg_line: 0,
+                reg_view_font: &self.debug_font,
+                clip_stack: Vec::new(),
+            };
+            self.wlctx.one_step(now_time as i64, [sz.0, sz.1], &mut p);
             self.wlctx.show_debug_registers(&mut p);
         }
 
@@ -241,8 +315,75 @@ impl EventHandler for WCtrDemEngine {
     }
 }
 
+fn parse_secs_to_ms(s: &str) -> i64 {
+    (s.parse::<f64>().expect("bad time in seconds") * 1000.0) as i64
+}
+
+/// Parses the command line:
+///
+///     wcdemengine [script.wl] [--render <dir> | --render-y4m <file>]
+///                 [--fps <n>] [--start <secs>] [--end <secs>]
+///                 [--size <w>x<h>]
+///
+/// Returns the script filename and the headless render settings,
+/// if any of the `--render` options was given.
+fn parse_args() -> (String, Option<RenderSettings>) {
+    let mut script = String::from("in.wl");
+    let mut rs     = RenderSettings::new();
+    let mut render = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut next_arg = ||
+            args.next().unwrap_or_else(|| panic!("{} expects an argument", arg));
+
+        match &arg[..] {
+            "--render" => {
+                rs.output = FrameOutput::PngDir(next_arg());
+                render = true;
+            },
+            "--render-y4m" => {
+                rs.output = FrameOutput::Y4M(next_arg());
+                render = true;
+            },
+            "--fps"   => { rs.fps = next_arg().parse().expect("bad --fps"); },
+            "--start" => { rs.start_ms = parse_secs_to_ms(&next_arg()); },
+            "--end"   => { rs.end_ms   = parse_secs_to_ms(&next_arg()); },
+            "--size"  => {
+                let size = next_arg();
+                let wh : Vec<&str> = size.split('x').collect();
+                if wh.len() != 2 { panic!("bad --size '{}', expected WxH", size); }
+                rs.width  = wh[0].parse().expect("bad --size width");
+                rs.height = wh[1].parse().expect("bad --size height");
+            },
+            opt if opt.starts_with("--") => {
+                panic!("unknown option '{}'", opt);
+            },
+            _ => { script = arg.clone(); },
+        }
+    }
+
+    (script, if render { Some(rs) } else { None })
+}
 
 fn main() {
+    let (script, render_settings) = parse_args();
+
+    if let Some(rs) = render_settings {
+        let mut wlctx = WLambdaCtx::new();
+        wlctx.init();
+        wlctx.load_script(&script);
+
+        match headless::render_frames(&mut wlctx, &rs) {
+            Ok(_)  => eprintln!("Rendered {} frames.", rs.frame_count()),
+            Err(e) => {
+                eprintln!("Error occured while rendering: {}", e);
+                std::process::exit(1);
+            },
+        }
+        return;
+    }
+
     // Make a Context and an EventLoop.
     let (mut ctx, mut event_loop) =
        ContextBuilder::new("wctr_dem_engine", "Weird Constructor")
@@ -269,7 +410,7 @@ fn main() {
     // Create an instance of your event handler.
     // Usually, you should provide it with the Context object
     // so it can load resources like images during setup.
-    let mut engine = WCtrDemEngine::new(&mut ctx);
+    let mut engine = WCtrDemEngine::new(&mut ctx, &script);
 
     // Run!
     match event::run(&mut ctx, &mut event_loop, &mut engine) {
@@ -0,0 +1,183 @@
+use crate::turtle::next_xoroshiro128;
+
+// Simplex noise after the public domain reference implementation
+// by Stefan Gustavson ("Simplex noise demystified", 2005).
+
+const GRAD3 : [[f32; 3]; 12] = [
+    [ 1.0,  1.0,  0.0], [-1.0,  1.0,  0.0], [ 1.0, -1.0,  0.0], [-1.0, -1.0,  0.0],
+    [ 1.0,  0.0,  1.0], [-1.0,  0.0,  1.0], [ 1.0,  0.0, -1.0], [-1.0,  0.0, -1.0],
+    [ 0.0,  1.0,  1.0], [ 0.0, -1.0,  1.0], [ 0.0,  1.0, -1.0], [ 0.0, -1.0, -1.0],
+];
+
+/// Seeded simplex noise in 1, 2 and 3 dimensions,
+/// the results are in the range -1.0 to 1.0.
+#[derive(Clone)]
+pub struct SimplexNoise {
+    perm: [u8; 512],
+}
+
+fn fast_floor(x: f32) -> i32 {
+    let xi = x as i32;
+    if x < xi as f32 { xi - 1 } else { xi }
+}
+
+impl SimplexNoise {
+    pub fn new(seed: u64) -> Self {
+        let mut p : [u8; 256] = [0; 256];
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+
+        let mut rng = [
+            seed.wrapping_add(0x193a6754a8a7d469),
+            seed.wrapping_mul(7).wrapping_add(0x97830e05113ba7bb),
+        ];
+        for i in (1..256).rev() {
+            let j = (next_xoroshiro128(&mut rng) % (i as u64 + 1)) as usize;
+            p.swap(i, j);
+        }
+
+        let mut perm = [0; 512];
+        for i in 0..512 {
+            perm[i] = p[i & 255];
+        }
+        SimplexNoise { perm }
+    }
+
+    fn perm(&self, i: i32) -> usize {
+        self.perm[(i & 511) as usize] as usize
+    }
+
+    pub fn noise1(&self, x: f32) -> f32 {
+        let i0 = fast_floor(x);
+        let x0 = x - i0 as f32;
+        let x1 = x0 - 1.0;
+
+        let grad = |h: usize, x: f32| {
+            let g = 1.0 + (h & 7) as f32;
+            if h & 8 != 0 { -g * x } else { g * x }
+        };
+
+        let t0 = 1.0 - x0 * x0;
+        let t1 = 1.0 - x1 * x1;
+        let n0 = t0 * t0 * t0 * t0 * grad(self.perm(i0 & 255), x0);
+        let n1 = t1 * t1 * t1 * t1 * grad(self.perm((i0 + 1) & 255), x1);
+
+        0.395 * (n0 + n1)
+    }
+
+    pub fn noise2(&self, x: f32, y: f32) -> f32 {
+        let f2 = 0.5 * ((3.0 as f32).sqrt() - 1.0);
+        let g2 = (3.0 - (3.0 as f32).sqrt()) / 6.0;
+
+        let s  = (x + y) * f2;
+        let i  = fast_floor(x + s);
+        let j  = fast_floor(y + s);
+        let t  = (i + j) as f32 * g2;
+        let x0 = x - (i as f32 - t);
+        let y0 = y - (j as f32 - t);
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let x1 = x0 - i1 as f32 + g2;
+        let y1 = y0 - j1 as f32 + g2;
+        let x2 = x0 - 1.0 + 2.0 * g2;
+        let y2 = y0 - 1.0 + 2.0 * g2;
+
+        let ii = i & 255;
+        let jj = j & 255;
+        let gi0 = self.perm(ii      + self.perm(jj)      as i32) % 12;
+        let gi1 = self.perm(ii + i1 + self.perm(jj + j1) as i32) % 12;
+        let gi2 = self.perm(ii + 1  + self.perm(jj + 1)  as i32) % 12;
+
+        let corner = |gi: usize, x: f32, y: f32| {
+            let t = 0.5 - x * x - y * y;
+            if t < 0.0 { 0.0 }
+            else { t * t * t * t * (GRAD3[gi][0] * x + GRAD3[gi][1] * y) }
+        };
+
+        70.0 * (corner(gi0, x0, y0) + corner(gi1, x1, y1) + corner(gi2, x2, y2))
+    }
+
+    pub fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let f3 = 1.0 / 3.0;
+        let g3 = 1.0 / 6.0;
+
+        let s  = (x + y + z) * f3;
+        let i  = fast_floor(x + s);
+        let j  = fast_floor(y + s);
+        let k  = fast_floor(z + s);
+        let t  = (i + j + k) as f32 * g3;
+        let x0 = x - (i as f32 - t);
+        let y0 = y - (j as f32 - t);
+        let z0 = z - (k as f32 - t);
+
+        let (i1, j1, k1, i2, j2, k2) =
+            if x0 >= y0 {
+                if y0 >= z0      { (1, 0, 0, 1, 1, 0) }
+                else if x0 >= z0 { (1, 0, 0, 1, 0, 1) }
+                else             { (0, 0, 1, 1, 0, 1) }
+            } else {
+                if y0 < z0       { (0, 0, 1, 0, 1, 1) }
+                else if x0 < z0  { (0, 1, 0, 0, 1, 1) }
+                else             { (0, 1, 0, 1, 1, 0) }
+            };
+
+        let x1 = x0 - i1 as f32 + g3;
+        let y1 = y0 - j1 as f32 + g3;
+        let z1 = z0 - k1 as f32 + g3;
+        let x2 = x0 - i2 as f32 + 2.0 * g3;
+        let y2 = y0 - j2 as f32 + 2.0 * g3;
+        let z2 = z0 - k2 as f32 + 2.0 * g3;
+        let x3 = x0 - 1.0 + 3.0 * g3;
+        let y3 = y0 - 1.0 + 3.0 * g3;
+        let z3 = z0 - 1.0 + 3.0 * g3;
+
+        let ii = i & 255;
+        let jj = j & 255;
+        let kk = k & 255;
+        let gi = |a: i32, b: i32, c: i32| {
+            self.perm(ii + a + self.perm(jj + b + self.perm(kk + c) as i32) as i32) % 12
+        };
+
+        let corner = |gi: usize, x: f32, y: f32, z: f32| {
+            let t = 0.6 - x * x - y * y - z * z;
+            if t < 0.0 { 0.0 }
+            else {
+                t * t * t * t
+                * (GRAD3[gi][0] * x + GRAD3[gi][1] * y + GRAD3[gi][2] * z)
+            }
+        };
+
+        32.0 * (corner(gi(0,  0,  0),  x0, y0, z0)
+              + corner(gi(i1, j1, k1), x1, y1, z1)
+              + corner(gi(i2, j2, k2), x2, y2, z2)
+              + corner(gi(1,  1,  1),  x3, y3, z3))
+    }
+
+    /// Sums `octaves` layers of noise, each one with the frequency
+    /// multiplied by `lacunarity` and the amplitude multiplied by `gain`.
+    /// The result is normalized by the sum of the absolute amplitudes,
+    /// so it stays in -1.0 to 1.0 for negative gains too.
+    pub fn fbm(&self, dims: usize, p: [f32; 3], octaves: usize, lacunarity: f32, gain: f32) -> f32 {
+        let mut sum     = 0.0;
+        let mut amp     = 1.0;
+        let mut amp_sum = 0.0;
+        let mut freq    = 1.0;
+
+        for _ in 0..octaves.max(1) {
+            let n =
+                match dims {
+                    1 => self.noise1(p[0] * freq),
+                    2 => self.noise2(p[0] * freq, p[1] * freq),
+                    _ => self.noise3(p[0] * freq, p[1] * freq, p[2] * freq),
+                };
+            sum     += n * amp;
+            amp_sum += amp.abs();
+            amp     *= gain;
+            freq    *= lacunarity;
+        }
+
+        if amp_sum > 0.0 { sum / amp_sum } else { 0.0 }
+    }
+}
@@ -0,0 +1,1359 @@
+use crate::signals::OpIn;
+use crate::tracker::{Interpolation, Tempo};
+use crate::turtle::{next_xoroshiro128, u64_to_open01};
+use crate::easing::Easing;
+use crate::noise::SimplexNoise;
+
+use wlambda::vval::VVal;
+
+use std::collections::VecDeque;
+
+/// A signal op, that is executed once per frame and writes its
+/// results into `output_count()` consecutive registers.
+///
+/// These ops live in the `SignalOps` host of the `ClContext` and are
+/// created and parameterized by the same `new` and `input` script
+/// functions as the `Simulator` ops.
+pub trait SignalOp {
+    fn output_count(&self) -> usize;
+    /// Sets the input `name`, returns false if there is no such input.
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool;
+    /// `t` is the frame time in milliseconds, the outputs
+    /// are written to `regs[out..(out + output_count())]`.
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize);
+    /// Called before `exec` with the current tempo of the tracker.
+    fn set_tempo(&mut self, _tempo: Tempo) { }
+}
+
+/// Creates a new op of the type `type_name`. `cfg` is the optional
+/// configuration argument of the `new` script function.
+/// Returns `Ok(None)` if there is no such op type.
+pub fn new_signal_op(type_name: &str, cfg: &VVal) -> Result<Option<Box<dyn SignalOp>>, String> {
+    let op : Box<dyn SignalOp> =
+        match type_name {
+            "gradient" => Box::new(GradientOp::new(cfg)?),
+            "rand"     => Box::new(RandOp::new(cfg)?),
+            "saw"      => Box::new(LfoOp::new(LfoShape::Saw)),
+            "tri"      => Box::new(LfoOp::new(LfoShape::Tri)),
+            "square"   => Box::new(LfoOp::new(LfoShape::Square)),
+            "pulse"    => Box::new(LfoOp::new(LfoShape::Pulse)),
+            "tween"    => Box::new(TweenOp::new(cfg)?),
+            "adsr"     => Box::new(AdsrOp::new(cfg)?),
+            "slew"     => Box::new(FilterOp::new(FilterKind::Slew)),
+            "smooth"   => Box::new(FilterOp::new(FilterKind::Smooth)),
+            "hipass"   => Box::new(FilterOp::new(FilterKind::HiPass)),
+            "sh"       => Box::new(SampleHoldOp::new()),
+            "quant"    => Box::new(QuantOp::new(cfg)?),
+            "clock"    => Box::new(ClockOp::new()),
+            "counter"  => Box::new(CounterOp::new(false)),
+            "integrate" => Box::new(CounterOp::new(true)),
+            "delay"    => Box::new(DelayOp::new(cfg)?),
+            "noise"    => Box::new(NoiseOp::new(cfg)?),
+            "spring"   => Box::new(SpringOp::new()),
+            "cmp"      => Box::new(CmpOp::new(cfg)?),
+            "and"      => Box::new(LogicOp::new(LogicKind::And)),
+            "or"       => Box::new(LogicOp::new(LogicKind::Or)),
+            "not"      => Box::new(LogicOp::new(LogicKind::Not)),
+            "select"   => Box::new(SelectOp::new(cfg)?),
+            _ => return Ok(None),
+        };
+    Ok(Some(op))
+}
+
+struct OpEntry {
+    idx: usize,
+    out: usize,
+    op:  Box<dyn SignalOp>,
+}
+
+/// The host of the `SignalOp`s. It shares the op indices and the
+/// registers with the `Simulator`. A `Simulator` op created with the index
+/// of a `SignalOp` removes that `SignalOp`. The other way around the
+/// `Simulator` op keeps running, but `input` only reaches the `SignalOp`.
+///
+/// The `SignalOps` are executed after all `Simulator` ops of a frame.
+/// So a `SignalOp` sees the `Simulator` outputs of the current frame,
+/// but a `Simulator` op that reads a `SignalOp` output gets the value
+/// of the previous frame.
+pub struct SignalOps {
+    ops: Vec<OpEntry>,
+}
+
+impl SignalOps {
+    pub fn new() -> Self {
+        SignalOps { ops: Vec::new() }
+    }
+
+    /// Creates a new op with the index `idx`. If it replaces an op with
+    /// at least as many outputs, the output registers of that op are reused,
+    /// otherwise they are allocated at the end of `regs`.
+    /// Returns the first output register, or `Ok(None)` if `type_name`
+    /// is not a type handled here.
+    pub fn new_op(&mut self, idx: usize, type_name: &str, cfg: &VVal, regs: &mut Vec<f32>)
+        -> Result<Option<usize>, String> {
+
+        let op =
+            match new_signal_op(type_name, cfg)? {
+                Some(op) => op,
+                None     => return Ok(None),
+            };
+
+        let prev =
+            self.ops.iter()
+                .find(|e| e.idx == idx)
+                .map(|e| (e.out, e.op.output_count()));
+
+        let out =
+            match prev {
+                Some((out, count)) if count >= op.output_count() => out,
+                _ => {
+                    let out = regs.len();
+                    regs.resize(out + op.output_count(), 0.0);
+                    out
+                },
+            };
+
+        self.remove_op(idx);
+        self.ops.push(OpEntry { idx, out, op });
+        Ok(Some(out))
+    }
+
+    /// Removes the op with the index `idx`, called when a `Simulator`
+    /// op takes over the index.
+    pub fn remove_op(&mut self, idx: usize) {
+        self.ops.retain(|e| e.idx != idx);
+    }
+
+    /// Returns `None` if there is no op with the index `idx`.
+    pub fn set_input(&mut self, idx: usize, name: &str, op_in: OpIn) -> Option<bool> {
+        self.ops.iter_mut()
+            .find(|e| e.idx == idx)
+            .map(|e| e.op.set_input(name, op_in))
+    }
+
+    pub fn exec(&mut self, t: f32, tempo: Tempo, regs: &mut [f32]) {
+        for e in self.ops.iter_mut() {
+            if e.out + e.op.output_count() > regs.len() { continue; }
+            e.op.set_tempo(tempo);
+            e.op.exec(t, regs, e.out);
+        }
+    }
+}
+
+fn const_in(v: f32) -> OpIn {
+    OpIn::vv2opin(VVal::Flt(v as f64)).unwrap()
+}
+
+#[derive(Debug, Clone)]
+struct ColorStop {
+    pos:    f32,
+    interp: Interpolation,
+    chans:  [f32; 4],
+}
+
+/// Maps the `pos` input to a color from a list of color stops.
+/// The four channels are written to four consecutive registers,
+/// they are interpreted in whatever color model the script uses
+/// them in.
+///
+/// The stops are passed as configuration to `new`:
+///
+///     new 3 :gradient $[
+///         $[0.0, :lerp,  0.0, 1.0, 0.0, 1.0],
+///         $[0.5, :sstep, 1.0, 1.0, 0.0, 1.0],
+///         $[1.0, :step,  1.0, 1.0, 1.0, 1.0]];
+///
+/// The interpolation of a stop applies between it and the next stop.
+pub struct GradientOp {
+    pos:   OpIn,
+    stops: Vec<ColorStop>,
+}
+
+impl GradientOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        if !cfg.is_vec() {
+            return Err(format!("Bad gradient stops '{}'", cfg.s()));
+        }
+
+        let mut stops = Vec::new();
+        let mut i = 0;
+        while let Some(stop) = cfg.at(i) {
+            let interp_name = stop.at(1).unwrap_or(VVal::Nul).s_raw();
+            let interp =
+                Interpolation::from_name(&interp_name).ok_or_else(||
+                    format!("Bad gradient interpolation '{}'", interp_name))?;
+
+            let chan = |j| stop.at(j).unwrap_or(VVal::Flt(1.0)).f() as f32;
+            stops.push(ColorStop {
+                pos:    stop.at(0).unwrap_or(VVal::Nul).f() as f32,
+                interp,
+                chans:  [chan(2), chan(3), chan(4), chan(5)],
+            });
+            i += 1;
+        }
+
+        if stops.is_empty() {
+            return Err(String::from("Gradient without color stops"));
+        }
+        stops.sort_by(|a, b|
+            a.pos.partial_cmp(&b.pos).unwrap_or(std::cmp::Ordering::Equal));
+
+        Ok(GradientOp {
+            pos: const_in(0.0),
+            stops,
+        })
+    }
+}
+
+impl SignalOp for GradientOp {
+    fn output_count(&self) -> usize { 4 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "pos" => { self.pos = op_in; true },
+            _     => false,
+        }
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let pos = self.pos.calc(regs) as f32;
+
+        let first = &self.stops[0];
+        let last  = &self.stops[self.stops.len() - 1];
+        let chans =
+            if pos <= first.pos {
+                first.chans
+            } else if pos >= last.pos {
+                last.chans
+            } else {
+                let i = self.stops.iter().rposition(|s| s.pos <= pos).unwrap_or(0);
+                let a = &self.stops[i];
+                let b = &self.stops[(i + 1).min(self.stops.len() - 1)];
+                let x =
+                    if b.pos > a.pos { (pos - a.pos) / (b.pos - a.pos) }
+                    else { 0.0 };
+
+                let mut c = [0.0; 4];
+                for j in 0..4 {
+                    c[j] = a.interp.interpolate(a.chans[j], b.chans[j], x);
+                }
+                c
+            };
+
+        regs[out..(out + 4)].copy_from_slice(&chans);
+    }
+}
+
+/// Returns a random value between -1.0 and 1.0 for the
+/// step `k` of the random sequence `seed`.
+fn rand_at(seed: u64, k: i64) -> f32 {
+    let k = k as u64;
+    let mut state = [
+        k.wrapping_add(seed).wrapping_add(0x193a6754a8a7d469),
+        k.wrapping_mul(7).wrapping_add(seed).wrapping_add(0x97830e05113ba7bb),
+    ];
+    next_xoroshiro128(&mut state);
+    next_xoroshiro128(&mut state);
+    (u64_to_open01(next_xoroshiro128(&mut state)) * 2.0 - 1.0) as f32
+}
+
+/// A random signal generator, which picks `freq` new random values
+/// per second. In smooth mode the values are interpolated, in step mode
+/// they are held. The output is `offs + amp * (-1.0 to 1.0)`.
+///
+/// The signal only depends on the time and the seed, so it is
+/// reproducible when rendering from an arbitrary start time.
+///
+///     new 4 :rand :smooth;
+///     input 4 :freq 2.0;
+pub struct RandOp {
+    smooth: bool,
+    freq:   OpIn,
+    amp:    OpIn,
+    offs:   OpIn,
+    seed:   OpIn,
+}
+
+impl RandOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let smooth =
+            match &cfg.s_raw()[..] {
+                "smooth" | "" => true,
+                "step"        => false,
+                m => return Err(format!("Bad rand mode '{}'", m)),
+            };
+
+        Ok(RandOp {
+            smooth,
+            freq: const_in(1.0),
+            amp:  const_in(1.0),
+            offs: const_in(0.0),
+            seed: const_in(0.0),
+        })
+    }
+}
+
+impl SignalOp for RandOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "freq" => { self.freq = op_in; true },
+            "amp"  => { self.amp  = op_in; true },
+            "offs" => { self.offs = op_in; true },
+            "seed" => { self.seed = op_in; true },
+            _      => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let freq = self.freq.calc(regs) as f32;
+        let seed = self.seed.calc(regs) as i64 as u64;
+
+        let phase = (t / 1000.0) * freq;
+        let k     = phase.floor();
+        let v =
+            if self.smooth {
+                Interpolation::SStep.interpolate(
+                    rand_at(seed, k as i64),
+                    rand_at(seed, k as i64 + 1),
+                    phase - k)
+            } else {
+                rand_at(seed, k as i64)
+            };
+
+        regs[out] =
+            self.offs.calc(regs) as f32
+            + self.amp.calc(regs) as f32 * v;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LfoShape {
+    Saw,
+    Tri,
+    Square,
+    Pulse,
+}
+
+/// Low frequency oscillators with the output `offs + amp * wave`,
+/// where the wave goes from -1.0 to 1.0. `freq` is in Hz and
+/// `phase` (0.0 to 1.0) shifts the wave. The `:pulse` shape is high for
+/// the fraction `width` of each period, `:square` is a pulse with
+/// the fixed width 0.5.
+///
+/// Note that the `freq` of the `:sin` op is not in Hz, it is driven
+/// with per millisecond values (like `:freq 0.0003` in in.wl). So replacing
+/// a `:sin` with one of these ops needs a different `freq` for the same
+/// speed. For example, 0.5 cycles per second is `:freq 0.5` here.
+pub struct LfoOp {
+    shape: LfoShape,
+    freq:  OpIn,
+    phase: OpIn,
+    amp:   OpIn,
+    offs:  OpIn,
+    width: OpIn,
+}
+
+impl LfoOp {
+    fn new(shape: LfoShape) -> Self {
+        LfoOp {
+            shape,
+            freq:  const_in(1.0),
+            phase: const_in(0.0),
+            amp:   const_in(1.0),
+            offs:  const_in(0.0),
+            width: const_in(0.5),
+        }
+    }
+}
+
+impl SignalOp for LfoOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "freq"  => { self.freq  = op_in; true },
+            "phase" => { self.phase = op_in; true },
+            "amp"   => { self.amp   = op_in; true },
+            "offs"  => { self.offs  = op_in; true },
+            "width" if self.shape == LfoShape::Pulse => {
+                self.width = op_in;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let freq  = self.freq.calc(regs) as f32;
+        let phase = self.phase.calc(regs) as f32;
+        let ph    = (t / 1000.0) * freq + phase;
+        let ph    = ph - ph.floor();
+
+        let wave =
+            match self.shape {
+                LfoShape::Saw    => 2.0 * ph - 1.0,
+                LfoShape::Tri    => 1.0 - 4.0 * (ph - 0.5).abs(),
+                LfoShape::Square => if ph < 0.5 { 1.0 } else { -1.0 },
+                LfoShape::Pulse  =>
+                    if ph < self.width.calc(regs) as f32 { 1.0 } else { -1.0 },
+            };
+
+        regs[out] =
+            self.offs.calc(regs) as f32
+            + self.amp.calc(regs) as f32 * wave;
+    }
+}
+
+/// Parses the easing curve from an op configuration,
+/// defaulting to `default` if none was given.
+fn cfg_easing(cfg: &VVal, default: Easing) -> Result<Easing, String> {
+    let name = cfg.s_raw();
+    if name.is_empty() { return Ok(default); }
+    Easing::from_name(&name).ok_or_else(|| format!("Bad easing curve '{}'", name))
+}
+
+/// Goes from the value `from` to `to` between the times `start` and
+/// `end` (in seconds) along an easing curve, which is passed as
+/// configuration to `new`. Before `start` the output is `from`,
+/// after `end` it is `to`.
+///
+///     new 5 :tween :bounce;
+///     input 5 :start 2.0;
+///     input 5 :end   3.5;
+pub struct TweenOp {
+    easing: Easing,
+    start:  OpIn,
+    end:    OpIn,
+    from:   OpIn,
+    to:     OpIn,
+}
+
+impl TweenOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        Ok(TweenOp {
+            easing: cfg_easing(cfg, Easing::Linear)?,
+            start:  const_in(0.0),
+            end:    const_in(1.0),
+            from:   const_in(0.0),
+            to:     const_in(1.0),
+        })
+    }
+}
+
+impl SignalOp for TweenOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "start" => { self.start = op_in; true },
+            "end"   => { self.end   = op_in; true },
+            "from"  => { self.from  = op_in; true },
+            "to"    => { self.to    = op_in; true },
+            _       => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let t     = t / 1000.0;
+        let start = self.start.calc(regs) as f32;
+        let end   = self.end.calc(regs) as f32;
+        let from  = self.from.calc(regs) as f32;
+        let to    = self.to.calc(regs) as f32;
+
+        let x =
+            if end > start { (t - start) / (end - start) }
+            else if t >= start { 1.0 }
+            else { 0.0 };
+
+        regs[out] = from + (to - from) * self.easing.ease(x);
+    }
+}
+
+/// Returns the time passed since the last call in seconds and
+/// remembers `t` (in milliseconds). The first call returns 0.0.
+fn step_dt(last_t: &mut Option<f32>, t: f32) -> f32 {
+    let dt =
+        match *last_t {
+            Some(lt) if t > lt => (t - lt) / 1000.0,
+            _ => 0.0,
+        };
+    *last_t = Some(t);
+    dt
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum AdsrStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// An envelope generator. When the `gate` input goes above 0.0 the
+/// output rises to 1.0 in `attack` seconds, falls to the `sustain` level
+/// in `decay` seconds and holds it until the gate goes down again.
+/// Then it falls to 0.0 in `release` seconds. The curve of the
+/// segments is an easing curve passed as configuration to `new`.
+///
+///     new 6 :adsr :quad_out;
+///     input 6 :gate $[:reg, clock_trig];
+pub struct AdsrOp {
+    easing:      Easing,
+    gate:        OpIn,
+    attack:      OpIn,
+    decay:       OpIn,
+    sustain:     OpIn,
+    release:     OpIn,
+    stage:       AdsrStage,
+    stage_time:  f32,
+    start_level: f32,
+    level:       f32,
+    gate_on:     bool,
+    last_t:      Option<f32>,
+}
+
+impl AdsrOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        Ok(AdsrOp {
+            easing:      cfg_easing(cfg, Easing::Linear)?,
+            gate:        const_in(0.0),
+            attack:      const_in(0.01),
+            decay:       const_in(0.1),
+            sustain:     const_in(0.5),
+            release:     const_in(0.3),
+            stage:       AdsrStage::Idle,
+            stage_time:  0.0,
+            start_level: 0.0,
+            level:       0.0,
+            gate_on:     false,
+            last_t:      None,
+        })
+    }
+
+    fn enter(&mut self, stage: AdsrStage) {
+        self.stage       = stage;
+        self.stage_time  = 0.0;
+        self.start_level = self.level;
+    }
+}
+
+impl SignalOp for AdsrOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "gate"    => { self.gate    = op_in; true },
+            "attack"  => { self.attack  = op_in; true },
+            "decay"   => { self.decay   = op_in; true },
+            "sustain" => { self.sustain = op_in; true },
+            "release" => { self.release = op_in; true },
+            _         => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let dt      = step_dt(&mut self.last_t, t);
+        let gate_on = self.gate.calc(regs) as f32 > 0.0;
+        let sustain = self.sustain.calc(regs) as f32;
+
+        if gate_on && !self.gate_on {
+            self.enter(AdsrStage::Attack);
+        } else if !gate_on && self.gate_on && self.stage != AdsrStage::Idle {
+            self.enter(AdsrStage::Release);
+        }
+        self.gate_on = gate_on;
+
+        self.stage_time += dt;
+
+        // loop, because short segments may be passed within one step
+        loop {
+            let (len, from, to, next) =
+                match self.stage {
+                    AdsrStage::Idle    => { self.level = 0.0;     break; },
+                    AdsrStage::Sustain => { self.level = sustain; break; },
+                    AdsrStage::Attack  =>
+                        (self.attack.calc(regs) as f32,
+                         self.start_level, 1.0, AdsrStage::Decay),
+                    AdsrStage::Decay   =>
+                        (self.decay.calc(regs) as f32,
+                         1.0, sustain, AdsrStage::Sustain),
+                    AdsrStage::Release =>
+                        (self.release.calc(regs) as f32,
+                         self.start_level, 0.0, AdsrStage::Idle),
+                };
+
+            if len > 0.0 && self.stage_time < len {
+                let x = self.easing.ease(self.stage_time / len);
+                self.level = from + (to - from) * x;
+                break;
+            }
+
+            let rest = if len > 0.0 { self.stage_time - len } else { self.stage_time };
+            self.level = to;
+            self.enter(next);
+            self.stage_time = rest;
+        }
+
+        regs[out] = self.level;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FilterKind {
+    Slew,
+    Smooth,
+    HiPass,
+}
+
+/// Filters for easing jumpy signals:
+///
+/// - `:slew` limits the rate of change of `in` to `up` per second when
+///   rising and `down` per second when falling.
+/// - `:smooth` is a one pole low pass with the time constant `time`
+///   in seconds.
+/// - `:hipass` outputs `in` minus its low pass with the time
+///   constant `time`, which removes slow drift.
+pub struct FilterOp {
+    kind:   FilterKind,
+    input:  OpIn,
+    up:     OpIn,
+    down:   OpIn,
+    time:   OpIn,
+    state:  Option<f32>,
+    last_t: Option<f32>,
+}
+
+impl FilterOp {
+    fn new(kind: FilterKind) -> Self {
+        FilterOp {
+            kind,
+            input:  const_in(0.0),
+            up:     const_in(1.0),
+            down:   const_in(1.0),
+            time:   const_in(0.1),
+            state:  None,
+            last_t: None,
+        }
+    }
+}
+
+impl SignalOp for FilterOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match (self.kind, name) {
+            (_, "in")                  => { self.input = op_in; true },
+            (FilterKind::Slew, "up")   => { self.up    = op_in; true },
+            (FilterKind::Slew, "down") => { self.down  = op_in; true },
+            (FilterKind::Smooth, "time")
+            | (FilterKind::HiPass, "time") => { self.time = op_in; true },
+            _ => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let dt = step_dt(&mut self.last_t, t);
+        let x  = self.input.calc(regs) as f32;
+        let y  = self.state.unwrap_or(x);
+
+        let y =
+            match self.kind {
+                FilterKind::Slew => {
+                    let max_up   = self.up.calc(regs).abs()   as f32 * dt;
+                    let max_down = self.down.calc(regs).abs() as f32 * dt;
+                    let d = x - y;
+                    if d > max_up         { y + max_up }
+                    else if d < -max_down { y - max_down }
+                    else                  { x }
+                },
+                FilterKind::Smooth | FilterKind::HiPass => {
+                    let tau = self.time.calc(regs) as f32;
+                    if tau <= 0.0 { x }
+                    else { y + (x - y) * (1.0 - (-dt / tau).exp()) }
+                },
+            };
+        self.state = Some(y);
+
+        regs[out] =
+            if self.kind == FilterKind::HiPass { x - y } else { y };
+    }
+}
+
+/// Returns true if `v` crossed zero upwards since the last call
+/// and remembers `v` in `last`.
+fn rising_edge(last: &mut f32, v: f32) -> bool {
+    let rising = *last <= 0.0 && v > 0.0;
+    *last = v;
+    rising
+}
+
+/// Latches the `in` input whenever the `trig` input crosses zero
+/// upwards and holds it until the next crossing.
+pub struct SampleHoldOp {
+    input:     OpIn,
+    trig:      OpIn,
+    last_trig: f32,
+    value:     f32,
+}
+
+impl SampleHoldOp {
+    fn new() -> Self {
+        SampleHoldOp {
+            input:     const_in(0.0),
+            trig:      const_in(0.0),
+            last_trig: 0.0,
+            value:     0.0,
+        }
+    }
+}
+
+impl SignalOp for SampleHoldOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "in"   => { self.input = op_in; true },
+            "trig" => { self.trig  = op_in; true },
+            _      => false,
+        }
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let trig = self.trig.calc(regs) as f32;
+        if rising_edge(&mut self.last_trig, trig) {
+            self.value = self.input.calc(regs) as f32;
+        }
+
+        regs[out] = self.value;
+    }
+}
+
+/// Snaps the `in` input to the nearest multiple of `step`, or, if a list
+/// of values is passed as configuration to `new`, to the nearest of
+/// those values:
+///
+///     new 8 :quant $[0.0, 0.25, 0.3, 1.0];
+pub struct QuantOp {
+    input:  OpIn,
+    step:   OpIn,
+    values: Vec<f32>,
+}
+
+impl QuantOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let mut values = Vec::new();
+        if cfg.is_vec() {
+            let mut i = 0;
+            while let Some(v) = cfg.at(i) {
+                values.push(v.f() as f32);
+                i += 1;
+            }
+            if values.is_empty() {
+                return Err(String::from("Empty value list for quant op"));
+            }
+        }
+
+        Ok(QuantOp {
+            input: const_in(0.0),
+            step:  const_in(0.1),
+            values,
+        })
+    }
+}
+
+impl SignalOp for QuantOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "in"   => { self.input = op_in; true },
+            "step" => { self.step  = op_in; true },
+            _      => false,
+        }
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let x = self.input.calc(regs) as f32;
+
+        regs[out] =
+            if !self.values.is_empty() {
+                let mut best = self.values[0];
+                for v in self.values.iter() {
+                    if (v - x).abs() < (best - x).abs() {
+                        best = *v;
+                    }
+                }
+                best
+            } else {
+                let step = self.step.calc(regs) as f32;
+                if step > 0.0 { (x / step).round() * step } else { x }
+            };
+    }
+}
+
+/// Derives the musical time from the tempo of the tracker.
+/// Has four outputs:
+///
+/// - the phase in the current beat (0.0 to 1.0)
+/// - the phase in the current bar of 4 beats (0.0 to 1.0)
+/// - the current row of the tracker
+/// - a trigger, which is 1.0 for one step at the start of each beat
+///   and 0.0 otherwise.
+pub struct ClockOp {
+    tempo:     Tempo,
+    last_beat: Option<i64>,
+}
+
+impl ClockOp {
+    fn new() -> Self {
+        ClockOp {
+            tempo:     Tempo { bpm: 120, rpb: 1, rows: 64 },
+            last_beat: None,
+        }
+    }
+}
+
+impl SignalOp for ClockOp {
+    fn output_count(&self) -> usize { 4 }
+
+    fn set_input(&mut self, _name: &str, _op_in: OpIn) -> bool { false }
+
+    fn set_tempo(&mut self, tempo: Tempo) {
+        self.tempo = tempo;
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let beats = (t / 1000.0) * (self.tempo.bpm as f32 / 60.0);
+        let beat  = beats.floor();
+        let bars  = beats / 4.0;
+
+        let row = (beats * self.tempo.rpb as f32).floor() as usize;
+        let row = if self.tempo.rows > 0 { row % self.tempo.rows } else { row };
+
+        let trig = self.last_beat.map(|b| b != beat as i64).unwrap_or(true);
+        self.last_beat = Some(beat as i64);
+
+        regs[out]     = beats - beat;
+        regs[out + 1] = bars - bars.floor();
+        regs[out + 2] = row as f32;
+        regs[out + 3] = if trig { 1.0 } else { 0.0 };
+    }
+}
+
+/// Keeps state across frames:
+///
+/// - `:counter` adds `step` whenever `trig` crosses zero upwards.
+/// - `:integrate` accumulates `in` multiplied by the time step in
+///   seconds, so a speed signal becomes a continuous position.
+///
+/// Both are set back to 0.0 when `reset` crosses zero upwards, and wrap
+/// around into the range 0.0 to `wrap`, if `wrap` is greater than 0.0.
+pub struct CounterOp {
+    integrate:  bool,
+    input:      OpIn,
+    trig:       OpIn,
+    step:       OpIn,
+    reset:      OpIn,
+    wrap:       OpIn,
+    last_trig:  f32,
+    last_reset: f32,
+    value:      f32,
+    last_t:     Option<f32>,
+}
+
+impl CounterOp {
+    fn new(integrate: bool) -> Self {
+        CounterOp {
+            integrate,
+            input:      const_in(0.0),
+            trig:       const_in(0.0),
+            step:       const_in(1.0),
+            reset:      const_in(0.0),
+            wrap:       const_in(0.0),
+            last_trig:  0.0,
+            last_reset: 0.0,
+            value:      0.0,
+            last_t:     None,
+        }
+    }
+}
+
+impl SignalOp for CounterOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match (self.integrate, name) {
+            (true,  "in")   => { self.input = op_in; true },
+            (false, "trig") => { self.trig  = op_in; true },
+            (false, "step") => { self.step  = op_in; true },
+            (_,     "reset") => { self.reset = op_in; true },
+            (_,     "wrap")  => { self.wrap  = op_in; true },
+            _ => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let dt = step_dt(&mut self.last_t, t);
+
+        if self.integrate {
+            self.value += self.input.calc(regs) as f32 * dt;
+        } else {
+            let trig = self.trig.calc(regs) as f32;
+            if rising_edge(&mut self.last_trig, trig) {
+                self.value += self.step.calc(regs) as f32;
+            }
+        }
+
+        let reset = self.reset.calc(regs) as f32;
+        if rising_edge(&mut self.last_reset, reset) {
+            self.value = 0.0;
+        }
+
+        let wrap = self.wrap.calc(regs) as f32;
+        if wrap > 0.0 {
+            self.value = self.value - (self.value / wrap).floor() * wrap;
+        }
+
+        regs[out] = self.value;
+    }
+}
+
+/// Keeps the history of the `in` input for the last N seconds
+/// (configuration to `new`, defaults to 2 seconds) and outputs the
+/// value it had `time` seconds ago, interpolated between the recorded
+/// steps. Before enough history has been recorded, the oldest
+/// value is returned.
+///
+///     new 9 :delay 5.0;
+///     input 9 :in   $[:reg, leader_x];
+///     input 9 :time 0.25;
+///
+/// This gives one delayed value per op. Sampling the history at a
+/// different offset per shape (for followers and trailing shapes)
+/// needs an `OpIn` form, which is not implemented yet.
+pub struct DelayOp {
+    input:   OpIn,
+    time:    OpIn,
+    max_len: f32,
+    /// (time in seconds, value), oldest first
+    history: VecDeque<(f32, f32)>,
+}
+
+impl DelayOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let max_len = if cfg.is_none() { 2.0 } else { cfg.f() as f32 };
+        if max_len <= 0.0 {
+            return Err(format!("Bad delay length '{}'", cfg.s()));
+        }
+
+        Ok(DelayOp {
+            input:   const_in(0.0),
+            time:    const_in(0.0),
+            max_len,
+            history: VecDeque::new(),
+        })
+    }
+
+    fn value_at(&self, t: f32) -> f32 {
+        let idx =
+            match self.history.iter().rposition(|(ht, _)| *ht <= t) {
+                Some(i) => i,
+                None    => return self.history.front().map(|h| h.1).unwrap_or(0.0),
+            };
+
+        let (t0, v0) = self.history[idx];
+        if let Some((t1, v1)) = self.history.get(idx + 1) {
+            if *t1 > t0 {
+                return v0 + (v1 - v0) * ((t - t0) / (t1 - t0));
+            }
+        }
+        v0
+    }
+}
+
+impl SignalOp for DelayOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "in"   => { self.input = op_in; true },
+            "time" => { self.time  = op_in; true },
+            _      => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let t = t / 1000.0;
+
+        // time went backwards, eg. the demo was restarted:
+        if self.history.back().map(|h| h.0 > t).unwrap_or(false) {
+            self.history.clear();
+        }
+
+        self.history.push_back((t, self.input.calc(regs) as f32));
+        while self.history.len() > 2
+              && self.history[1].0 < t - self.max_len {
+            self.history.pop_front();
+        }
+
+        let delay = (self.time.calc(regs) as f32).max(0.0).min(self.max_len);
+        regs[out] = self.value_at(t - delay);
+    }
+}
+
+/// Coherent simplex noise with 1, 2 or 3 dimensions (configuration
+/// to `new`, defaults to 1). The noise is sampled at the coordinates
+/// `x`, `y` and `z`, where `x` is moved by `speed` units per second.
+/// `octaves`, `lacunarity` and `gain` control the layering of the noise,
+/// the output is `offs + amp * (-1.0 to 1.0)`.
+///
+///     new 10 :noise 2;
+///     input 10 :y     $[:reg, s1];
+///     input 10 :speed 0.5;
+pub struct NoiseOp {
+    dims:       usize,
+    x:          OpIn,
+    y:          OpIn,
+    z:          OpIn,
+    speed:      OpIn,
+    octaves:    OpIn,
+    lacunarity: OpIn,
+    gain:       OpIn,
+    seed:       OpIn,
+    amp:        OpIn,
+    offs:       OpIn,
+    noise:      SimplexNoise,
+    noise_seed: u64,
+}
+
+impl NoiseOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let dims = if cfg.is_none() { 1 } else { cfg.i() };
+        if dims < 1 || dims > 3 {
+            return Err(format!("Bad noise dimensions '{}'", cfg.s()));
+        }
+
+        Ok(NoiseOp {
+            dims:       dims as usize,
+            x:          const_in(0.0),
+            y:          const_in(0.0),
+            z:          const_in(0.0),
+            speed:      const_in(1.0),
+            octaves:    const_in(1.0),
+            lacunarity: const_in(2.0),
+            gain:       const_in(0.5),
+            seed:       const_in(0.0),
+            amp:        const_in(1.0),
+            offs:       const_in(0.0),
+            noise:      SimplexNoise::new(0),
+            noise_seed: 0,
+        })
+    }
+}
+
+impl SignalOp for NoiseOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "x"          => { self.x          = op_in; true },
+            "y"          => { self.y          = op_in; true },
+            "z"          => { self.z          = op_in; true },
+            "speed"      => { self.speed      = op_in; true },
+            "octaves"    => { self.octaves    = op_in; true },
+            "lacunarity" => { self.lacunarity = op_in; true },
+            "gain"       => { self.gain       = op_in; true },
+            "seed"       => { self.seed       = op_in; true },
+            "amp"        => { self.amp        = op_in; true },
+            "offs"       => { self.offs       = op_in; true },
+            _            => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        let seed = self.seed.calc(regs) as i64 as u64;
+        if seed != self.noise_seed {
+            self.noise      = SimplexNoise::new(seed);
+            self.noise_seed = seed;
+        }
+
+        let p = [
+            self.x.calc(regs) as f32
+            + (t / 1000.0) * self.speed.calc(regs) as f32,
+            self.y.calc(regs) as f32,
+            self.z.calc(regs) as f32,
+        ];
+        let octaves = (self.octaves.calc(regs) as f32).round().max(1.0) as usize;
+
+        let n = self.noise.fbm(
+            self.dims, p, octaves.min(16),
+            self.lacunarity.calc(regs) as f32,
+            self.gain.calc(regs) as f32);
+
+        regs[out] =
+            self.offs.calc(regs) as f32
+            + self.amp.calc(regs) as f32 * n;
+    }
+}
+
+/// A damped spring, that pulls its position towards `target`.
+/// `stiffness` is the spring constant and `damping` the velocity
+/// damping, both per second. Outputs the position and the velocity.
+///
+///     new 11 :spring;
+///     input 11 :target    $[:reg, trk_x];
+///     input 11 :stiffness 120.0;
+///     input 11 :damping   8.0;
+pub struct SpringOp {
+    target:    OpIn,
+    stiffness: OpIn,
+    damping:   OpIn,
+    pos:       Option<f32>,
+    vel:       f32,
+    last_t:    Option<f32>,
+}
+
+impl SpringOp {
+    fn new() -> Self {
+        SpringOp {
+            target:    const_in(0.0),
+            stiffness: const_in(100.0),
+            damping:   const_in(10.0),
+            pos:       None,
+            vel:       0.0,
+            last_t:    None,
+        }
+    }
+}
+
+impl SignalOp for SpringOp {
+    fn output_count(&self) -> usize { 2 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "target"    => { self.target    = op_in; true },
+            "stiffness" => { self.stiffness = op_in; true },
+            "damping"   => { self.damping   = op_in; true },
+            _           => false,
+        }
+    }
+
+    fn exec(&mut self, t: f32, regs: &mut [f32], out: usize) {
+        // fixed sub steps keep stiff springs stable at low frame rates
+        const SUB_STEP : f32 = 1.0 / 240.0;
+
+        let dt        = step_dt(&mut self.last_t, t).min(1.0);
+        let target    = self.target.calc(regs) as f32;
+        let stiffness = self.stiffness.calc(regs) as f32;
+        let damping   = self.damping.calc(regs) as f32;
+
+        let mut pos = self.pos.unwrap_or(target);
+        let mut rest = dt;
+        while rest > 0.0 {
+            let h = rest.min(SUB_STEP);
+            let acc = stiffness * (target - pos) - damping * self.vel;
+            self.vel += acc * h;
+            pos      += self.vel * h;
+            rest     -= h;
+        }
+        self.pos = Some(pos);
+
+        regs[out]     = pos;
+        regs[out + 1] = self.vel;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CmpKind {
+    Lt,
+    Gt,
+    Eq,
+}
+
+/// Compares the inputs `a` and `b` and outputs 1.0 if the comparison
+/// (`:lt`, `:gt` or `:eq`, configuration to `new`) holds, 0.0
+/// otherwise. For `:lt` and `:gt` the output only switches, if the
+/// difference exceeds half of `hyst`, which avoids flickering of noisy
+/// signals. For `:eq` `hyst` is the tolerance.
+///
+///     new 12 :cmp :gt;
+///     input 12 :a    $[:reg, s1];
+///     input 12 :b    0.5;
+///     input 12 :hyst 0.1;
+pub struct CmpOp {
+    kind:  CmpKind,
+    a:     OpIn,
+    b:     OpIn,
+    hyst:  OpIn,
+    state: bool,
+}
+
+impl CmpOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let kind =
+            match &cfg.s_raw()[..] {
+                "lt"      => CmpKind::Lt,
+                "gt" | "" => CmpKind::Gt,
+                "eq"      => CmpKind::Eq,
+                m => return Err(format!("Bad cmp mode '{}'", m)),
+            };
+
+        Ok(CmpOp {
+            kind,
+            a:     const_in(0.0),
+            b:     const_in(0.0),
+            hyst:  const_in(0.0),
+            state: false,
+        })
+    }
+}
+
+impl SignalOp for CmpOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "a"    => { self.a    = op_in; true },
+            "b"    => { self.b    = op_in; true },
+            "hyst" => { self.hyst = op_in; true },
+            _      => false,
+        }
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let a    = self.a.calc(regs) as f32;
+        let b    = self.b.calc(regs) as f32;
+        let hyst = (self.hyst.calc(regs) as f32).abs();
+
+        // positive, if the comparison holds
+        let d =
+            match self.kind {
+                CmpKind::Gt => a - b,
+                CmpKind::Lt => b - a,
+                CmpKind::Eq => hyst - (a - b).abs(),
+            };
+
+        self.state =
+            if self.kind == CmpKind::Eq { d >= 0.0 }
+            else if self.state { d >= -hyst / 2.0 }
+            else { d > hyst / 2.0 };
+
+        regs[out] = if self.state { 1.0 } else { 0.0 };
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum LogicKind {
+    And,
+    Or,
+    Not,
+}
+
+/// Boolean logic on signals. An input counts as true, if it is above
+/// the threshold `thres` (defaults to 0.5). Outputs 1.0 or 0.0.
+/// `:not` only has the input `a`.
+pub struct LogicOp {
+    kind:  LogicKind,
+    a:     OpIn,
+    b:     OpIn,
+    thres: OpIn,
+}
+
+impl LogicOp {
+    fn new(kind: LogicKind) -> Self {
+        LogicOp {
+            kind,
+            a:     const_in(0.0),
+            b:     const_in(0.0),
+            thres: const_in(0.5),
+        }
+    }
+}
+
+impl SignalOp for LogicOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        match name {
+            "a"                                 => { self.a     = op_in; true },
+            "b" if self.kind != LogicKind::Not  => { self.b     = op_in; true },
+            "thres"                             => { self.thres = op_in; true },
+            _                                   => false,
+        }
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let thres = self.thres.calc(regs) as f32;
+        let a     = self.a.calc(regs) as f32 > thres;
+        let b     = self.b.calc(regs) as f32 > thres;
+
+        let res =
+            match self.kind {
+                LogicKind::And => a && b,
+                LogicKind::Or  => a || b,
+                LogicKind::Not => !a,
+            };
+
+        regs[out] = if res { 1.0 } else { 0.0 };
+    }
+}
+
+/// Outputs one of N inputs (`in0`, `in1`, ...), chosen by the rounded
+/// `idx` input, which is clamped to the valid range.
+/// N is the configuration to `new`, defaults to 2 and is at most
+/// `MAX_SELECT_INPUTS`.
+///
+///     new 13 :select 3;
+///     input 13 :idx $[:reg, scene];
+///     input 13 :in0 $[:reg, s1];
+pub struct SelectOp {
+    idx:    OpIn,
+    inputs: Vec<OpIn>,
+}
+
+const MAX_SELECT_INPUTS : i64 = 256;
+
+impl SelectOp {
+    fn new(cfg: &VVal) -> Result<Self, String> {
+        let n = if cfg.is_none() { 2 } else { cfg.i() };
+        if n < 1 || n > MAX_SELECT_INPUTS {
+            return Err(format!("Bad select input count '{}'", cfg.s()));
+        }
+
+        Ok(SelectOp {
+            idx:    const_in(0.0),
+            inputs: (0..n).map(|_| const_in(0.0)).collect(),
+        })
+    }
+}
+
+impl SignalOp for SelectOp {
+    fn output_count(&self) -> usize { 1 }
+
+    fn set_input(&mut self, name: &str, op_in: OpIn) -> bool {
+        if name == "idx" {
+            self.idx = op_in;
+            return true;
+        }
+
+        if name.starts_with("in") {
+            if let Ok(i) = name[2..].parse::<usize>() {
+                if i < self.inputs.len() {
+                    self.inputs[i] = op_in;
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32], out: usize) {
+        let idx = (self.idx.calc(regs) as f32).round().max(0.0) as usize;
+        let idx = idx.min(self.inputs.len() - 1);
+        regs[out] = self.inputs[idx].calc(regs) as f32;
+    }
+}
@@ -0,0 +1,186 @@
+use crate::turtle::{TurtleDrawing, ShapeRotation, rotate_vec2};
+use crate::clip::ClipPolygon;
+use crate::util::clamp01;
+
+use std::fs::File;
+use std::io::BufWriter;
+
+/// A pure software implementation of `TurtleDrawing`, rendering into
+/// an RGBA framebuffer. Used for rendering frames on machines without
+/// an OpenGL context.
+///
+/// Like the `Painter` in `main.rs` the coordinate origin is in
+/// the center of the framebuffer.
+pub struct SoftRaster {
+    width:  usize,
+    height: usize,
+    origin: [f32; 2],
+    /// straight (non premultiplied) RGBA pixels, row by row
+    pixels: Vec<[f32; 4]>,
+    clip_stack: Vec<ClipPolygon>,
+}
+
+/// Signed distance of `p` to an axis aligned box of the
+/// half size `half` centered at the origin.
+fn box_distance(p: [f32; 2], half: [f32; 2]) -> f32 {
+    let dx = p[0].abs() - half[0];
+    let dy = p[1].abs() - half[1];
+    let outside =
+        (dx.max(0.0) * dx.max(0.0) + dy.max(0.0) * dy.max(0.0)).sqrt();
+    outside + dx.max(dy).min(0.0)
+}
+
+impl SoftRaster {
+    pub fn new(width: usize, height: usize) -> Self {
+        SoftRaster {
+            width,
+            height,
+            origin: [width as f32 / 2.0, height as f32 / 2.0],
+            pixels: vec![[0.0, 0.0, 0.0, 1.0]; width * height],
+            clip_stack: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+
+    pub fn clear(&mut self, color: [f32; 4]) {
+        for p in self.pixels.iter_mut() {
+            *p = color;
+        }
+    }
+
+    fn blend_pixel(&mut self, x: usize, y: usize, color: [f32; 4], coverage: f32) {
+        let a = clamp01(color[3] * coverage);
+        if a <= 0.0 { return; }
+
+        let dst = &mut self.pixels[y * self.width + x];
+        let out_a = a + dst[3] * (1.0 - a);
+        if out_a <= 0.0 {
+            *dst = [0.0; 4];
+            return;
+        }
+
+        for i in 0..3 {
+            dst[i] =
+                (color[i] * a + dst[i] * dst[3] * (1.0 - a)) / out_a;
+        }
+        dst[3] = out_a;
+    }
+
+    /// Fills a rotated shape described by a signed distance function.
+    /// `center` is in turtle coordinates, `radius` bounds the shape and
+    /// `dist` receives the pixel center in the shape's local
+    /// (unrotated) coordinate system.
+    fn fill_sdf<F>(&mut self, color: [f32; 4], center: [f32; 2], angle: f32, radius: f32, dist: F)
+        where F: Fn([f32; 2]) -> f32 {
+
+        let cx = center[0] + self.origin[0];
+        let cy = center[1] + self.origin[1];
+        let r  = radius + 1.0;
+
+        let x0 = (cx - r).floor().max(0.0) as usize;
+        let y0 = (cy - r).floor().max(0.0) as usize;
+        let x1 = ((cx + r).ceil().max(0.0) as usize).min(self.width);
+        let y1 = ((cy + r).ceil().max(0.0) as usize).min(self.height);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = [x as f32 + 0.5 - cx, y as f32 + 0.5 - cy];
+                let d = dist(rotate_vec2(p, -angle));
+                let mut coverage = clamp01(0.5 - d);
+
+                if let Some(clip) = self.clip_stack.last() {
+                    let cd = clip.distance([
+                        x as f32 + 0.5 - self.origin[0],
+                        y as f32 + 0.5 - self.origin[1],
+                    ]);
+                    coverage *= clamp01(0.5 - cd);
+                }
+                if coverage > 0.0 {
+                    self.blend_pixel(x, y, color, coverage);
+                }
+            }
+        }
+    }
+
+    /// Returns the framebuffer as 8 bit RGBA bytes.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pixels.len() * 4);
+        for p in self.pixels.iter() {
+            for c in p.iter() {
+                out.push((clamp01(*c) * 255.0).round() as u8);
+            }
+        }
+        out
+    }
+
+    pub fn write_png(&self, filename: &str) -> std::io::Result<()> {
+        let file = File::create(filename)?;
+        let mut encoder =
+            png::Encoder::new(
+                BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let to_io_err =
+            |e: png::EncodingError|
+                std::io::Error::new(std::io::ErrorKind::Other, e);
+
+        let mut writer = encoder.write_header().map_err(to_io_err)?;
+        writer.write_image_data(&self.to_rgba8()).map_err(to_io_err)
+    }
+}
+
+impl TurtleDrawing for SoftRaster {
+    fn draw_line(&mut self, color: [f32; 4], rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32) {
+        let (from, to) = rot.rotate_line(from, to, thickness);
+        let d   = [to[0] - from[0], to[1] - from[1]];
+        let len = (d[0] * d[0] + d[1] * d[1]).sqrt();
+        if len <= 0.0 { return; }
+
+        let center = [from[0] + d[0] / 2.0, from[1] + d[1] / 2.0];
+        let angle  = d[1].atan2(d[0]);
+        let half   = [len / 2.0, thickness / 2.0];
+
+        self.fill_sdf(
+            color, center, angle, half[0] + half[1],
+            |p| box_distance(p, half));
+    }
+
+    fn draw_rect_fill(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let angle  = rot.angle();
+        let center = rot.rect_center(pos, size);
+        let half   = [size[0].abs() / 2.0, size[1].abs() / 2.0];
+
+        self.fill_sdf(
+            color, center, angle, half[0] + half[1],
+            |p| box_distance(p, half));
+    }
+
+    fn draw_rect_outline(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2], thickness: f32) {
+        let angle  = rot.angle();
+        let center = rot.rect_center(pos, size);
+        let half   = [size[0].abs() / 2.0, size[1].abs() / 2.0];
+        let half_t = thickness.abs() / 2.0;
+
+        self.fill_sdf(
+            color, center, angle, half[0] + half[1] + half_t,
+            |p| box_distance(p, half).abs() - half_t);
+    }
+
+    fn push_clip(&mut self, rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let clip = ClipPolygon::from_rect(&rot, pos, size);
+        let clip =
+            if let Some(cur) = self.clip_stack.last() {
+                cur.intersect(&clip)
+            } else {
+                clip
+            };
+        self.clip_stack.push(clip);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clip_stack.pop();
+    }
+}
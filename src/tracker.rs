@@ -1,3 +1,5 @@
+use crate::util::clamp01;
+
 pub struct Tracker {
     /// beats per minute
     bpm:            usize,
@@ -8,6 +10,14 @@ pub struct Tracker {
     tracks:         Vec<Track>,
 }
 
+/// The musical timing of the `Tracker`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tempo {
+    pub bpm:  usize,
+    pub rpb:  usize,
+    pub rows: usize,
+}
+
 impl Tracker {
     pub fn new() -> Self {
         Tracker {
@@ -17,6 +27,15 @@ impl Tracker {
             tracks: Vec::new(),
         }
     }
+
+    pub fn tempo(&self) -> Tempo {
+        Tempo { bpm: self.bpm, rpb: self.rpb, rows: self.rows }
+    }
+
+    pub fn set_tempo(&mut self, bpm: usize, rpb: usize) {
+        self.bpm = bpm;
+        self.rpb = rpb.max(1);
+    }
 }
 
 pub struct TrackerEditor<'a> {
@@ -151,6 +170,32 @@ pub enum Interpolation {
     Exp,
 }
 
+impl Interpolation {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "step"  => Some(Interpolation::Step),
+            "lerp"  => Some(Interpolation::Lerp),
+            "sstep" => Some(Interpolation::SStep),
+            "exp"   => Some(Interpolation::Exp),
+            _       => None,
+        }
+    }
+
+    /// Interpolates from `a` to `b`, `x` goes from 0.0 to 1.0.
+    pub fn interpolate(&self, a: f32, b: f32, x: f32) -> f32 {
+        let x = clamp01(x);
+        let f =
+            match self {
+                Interpolation::Empty | Interpolation::Step => 0.0,
+                Interpolation::Lerp  => x,
+                Interpolation::SStep => x * x * (3.0 - 2.0 * x),
+                Interpolation::Exp   =>
+                    if x <= 0.0 { 0.0 } else { (2.0 as f32).powf(10.0 * (x - 1.0)) },
+            };
+        a + (b - a) * f
+    }
+}
+
 pub struct Track {
     name:     String,
     last_idx: usize,
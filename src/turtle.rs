@@ -1,6 +1,6 @@
 use vecmath;
 use crate::signals::OpIn;
-use crate::signals::ColorIn;
+use crate::color::ModelColorIn;
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -26,21 +26,139 @@ use std::cell::RefCell;
 pub enum Turtle {
     Commands(Vec<Turtle>),
     LookDir(OpIn, OpIn),
+    Anchor(OpIn, OpIn),
+    /// Stores position (in turtle units) and heading angle
+    /// into the registers (x, y, angle). The angle is in radians
+    /// in the range 0.0 to 2*PI, 0.0 is the initial direction (0, 1)
+    /// and it grows towards (-1, 0), which is clockwise on screen.
+    StoreState(usize, usize, usize),
     WithState(Box<Turtle>),
-    Rect(OpIn, OpIn, ColorIn),
-    RectLine(OpIn, OpIn, OpIn, ColorIn),
-    Line(OpIn, OpIn, ColorIn),
+    /// Restricts the drawing of the sub program to a rectangle (w, h)
+    /// at the turtle position, rotated with the turtle direction.
+    Clip(OpIn, OpIn, Box<Turtle>),
+    /// Executes the sub program n times rotated around the turtle
+    /// position (and additionally mirrored at the turtle direction,
+    /// if the flag is set). The copy index is written to the register.
+    Symmetry(OpIn, bool, usize, Box<Turtle>),
+    /// Executes the sub program count times, with a private copy of
+    /// the registers. The 3 registers starting at the given index
+    /// receive the instance id, the normalized id (0.0 to 1.0) and
+    /// a random value (0.0 to 1.0) that is stable per instance id.
+    Instances(OpIn, usize, Box<Turtle>),
+    /// Draws the sub program on the given layer. Layers with
+    /// a higher number are drawn on top.
+    Layer(OpIn, Box<Turtle>),
+    Rect(OpIn, OpIn, ModelColorIn),
+    RectLine(OpIn, OpIn, OpIn, ModelColorIn),
+    Line(OpIn, OpIn, ModelColorIn),
     SeedRand(OpIn),
     SeedGRand(OpIn),
     NextRand(usize, usize),
     NextGRand(usize, usize),
 }
 
+/// Describes the rotation angle of a shape and the pivot point
+/// it is rotated around. The pivot point is the position that is passed
+/// to the `TurtleDrawing` functions.
+///
+/// The pivot is defined in the local (unrotated) coordinate system of
+/// the shape, normalized to its size: `[0.0, 0.0]` is the left top corner
+/// and `[1.0, 1.0]` the right bottom corner. For lines the local
+/// Y axis goes along the line, from the start to the end point, and the
+/// X axis goes across the thickness.
 #[derive(Debug, PartialEq, Clone)]
 pub enum ShapeRotation {
-    LeftBottom(f32),
-//    TopRight(f32),
     Center(f32),
+    LeftTop(f32),
+    RightTop(f32),
+    LeftBottom(f32),
+    RightBottom(f32),
+    /// Arbitrary normalized pivot point and the rotation angle.
+    Anchor([f32; 2], f32),
+}
+
+/// Rotates `v` by `angle` (in radians) around the origin.
+pub fn rotate_vec2(v: [f32; 2], angle: f32) -> [f32; 2] {
+    let (s, c) = angle.sin_cos();
+    [v[0] * c - v[1] * s,
+     v[0] * s + v[1] * c]
+}
+
+impl ShapeRotation {
+    /// Returns the variant that matches the normalized `anchor`.
+    pub fn from_anchor(anchor: [f32; 2], angle: f32) -> Self {
+        match (anchor[0], anchor[1]) {
+            (x, y) if x == 0.5 && y == 0.5 => ShapeRotation::Center(angle),
+            (x, y) if x == 0.0 && y == 0.0 => ShapeRotation::LeftTop(angle),
+            (x, y) if x == 1.0 && y == 0.0 => ShapeRotation::RightTop(angle),
+            (x, y) if x == 0.0 && y == 1.0 => ShapeRotation::LeftBottom(angle),
+            (x, y) if x == 1.0 && y == 1.0 => ShapeRotation::RightBottom(angle),
+            _ => ShapeRotation::Anchor(anchor, angle),
+        }
+    }
+
+    pub fn angle(&self) -> f32 {
+        match self {
+            ShapeRotation::Center(a)      => *a,
+            ShapeRotation::LeftTop(a)     => *a,
+            ShapeRotation::RightTop(a)    => *a,
+            ShapeRotation::LeftBottom(a)  => *a,
+            ShapeRotation::RightBottom(a) => *a,
+            ShapeRotation::Anchor(_, a)   => *a,
+        }
+    }
+
+    /// The normalized pivot point of the shape.
+    pub fn anchor(&self) -> [f32; 2] {
+        match self {
+            ShapeRotation::Center(_)      => [0.5, 0.5],
+            ShapeRotation::LeftTop(_)     => [0.0, 0.0],
+            ShapeRotation::RightTop(_)    => [1.0, 0.0],
+            ShapeRotation::LeftBottom(_)  => [0.0, 1.0],
+            ShapeRotation::RightBottom(_) => [1.0, 1.0],
+            ShapeRotation::Anchor(p, _)   => *p,
+        }
+    }
+
+    /// Returns the unrotated offset of the left top corner of a
+    /// rectangle of `size` relative to the pivot point.
+    pub fn rect_offset(&self, size: [f32; 2]) -> [f32; 2] {
+        let a = self.anchor();
+        [-a[0] * size[0], -a[1] * size[1]]
+    }
+
+    /// Returns the center of a rectangle of `size` with
+    /// the pivot point at `pos`, after rotation.
+    pub fn rect_center(&self, pos: [f32; 2], size: [f32; 2]) -> [f32; 2] {
+        let a = self.anchor();
+        let c = rotate_vec2(
+            [(0.5 - a[0]) * size[0], (0.5 - a[1]) * size[1]],
+            self.angle());
+        [pos[0] + c[0], pos[1] + c[1]]
+    }
+
+    /// Rotates the line from `from` to `to` with the given `thickness`
+    /// around its pivot point and returns the new end points.
+    pub fn rotate_line(&self, from: [f32; 2], to: [f32; 2], thickness: f32) -> ([f32; 2], [f32; 2]) {
+        let angle = self.angle();
+        if angle == 0.0 { return (from, to); }
+
+        let a    = self.anchor();
+        let d    = [to[0] - from[0], to[1] - from[1]];
+        let len  = (d[0] * d[0] + d[1] * d[1]).sqrt();
+        let perp = if len > 0.0 { [-d[1] / len, d[0] / len] } else { [0.0, 0.0] };
+        let across = (a[0] - 0.5) * thickness;
+        let pivot = [
+            from[0] + d[0] * a[1] + perp[0] * across,
+            from[1] + d[1] * a[1] + perp[1] * across,
+        ];
+
+        let rot = |p: [f32; 2]| {
+            let r = rotate_vec2([p[0] - pivot[0], p[1] - pivot[1]], angle);
+            [pivot[0] + r[0], pivot[1] + r[1]]
+        };
+        (rot(from), rot(to))
+    }
 }
 
 #[derive(Clone)]
@@ -49,6 +167,8 @@ pub struct TurtleState {
     h:          f32,
     pos:        [f32; 2],
     dir:        [f32; 2],
+    /// normalized pivot point of the drawn rectangles
+    anchor:     [f32; 2],
     rand:       [[u64; 2]; 10],
     randg:      Rc<RefCell<[[u64; 2];100]>>,
 }
@@ -86,6 +206,7 @@ impl TurtleState {
             h,
             pos: [0.0, 0.0],
             dir: [0.0, 1.0],
+            anchor: [0.5, 0.5],
             rand: [[0; 2]; 10],
             randg: Rc::new(RefCell::new([[0; 2]; 100])),
         }
@@ -103,12 +224,97 @@ impl TurtleState {
         - ((1.0 as f32).atan2(0.0)
            - self.dir[1].atan2(self.dir[0]))
     }
+
+    /// The direction angle normalized to 0.0 <= angle < 2*PI,
+    /// see also `Turtle::StoreState`.
+    pub fn get_heading(&self) -> f32 {
+        let tau = 2.0 * std::f32::consts::PI;
+        let a   = self.get_direction_angle().rem_euclid(tau);
+        if a >= tau { 0.0 } else { a }
+    }
 }
 
 pub trait TurtleDrawing {
     fn draw_line(&mut self, color: [f32; 4], rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32);
     fn draw_rect_fill(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]);
     fn draw_rect_outline(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2], thickness: f32);
+    /// Restricts all following drawing to the given rectangle,
+    /// intersected with the currently active clip region.
+    fn push_clip(&mut self, rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]);
+    /// Restores the clip region that was active before the last `push_clip`.
+    fn pop_clip(&mut self);
+    /// Sets the layer for the following primitives and returns the
+    /// previous layer. Backends that draw immediately ignore the layers
+    /// and draw in program order, see also `layers::LayerRecorder`.
+    fn set_layer(&mut self, _layer: i64) -> i64 { 0 }
+}
+
+/// A `TurtleDrawing` adapter that rotates (and optionally mirrors)
+/// everything drawn around a pivot point, used by `Turtle::Symmetry`.
+struct SymmetryDrawing<'a> {
+    inner:  &'a mut dyn TurtleDrawing,
+    pivot:  [f32; 2],
+    angle:  f32,
+    /// angle of the mirror axis through the pivot
+    mirror: Option<f32>,
+}
+
+impl<'a> SymmetryDrawing<'a> {
+    fn transform_point(&self, p: [f32; 2]) -> [f32; 2] {
+        let mut v = [p[0] - self.pivot[0], p[1] - self.pivot[1]];
+        if let Some(axis) = self.mirror {
+            v = rotate_vec2([v[0], -v[1]], 2.0 * axis);
+        }
+        let v = rotate_vec2(v, self.angle);
+        [self.pivot[0] + v[0], self.pivot[1] + v[1]]
+    }
+
+    fn transform_rot(&self, rot: &ShapeRotation) -> ShapeRotation {
+        let anchor = rot.anchor();
+        if let Some(axis) = self.mirror {
+            ShapeRotation::from_anchor(
+                [anchor[0], 1.0 - anchor[1]],
+                self.angle + 2.0 * axis - rot.angle())
+        } else {
+            ShapeRotation::from_anchor(anchor, self.angle + rot.angle())
+        }
+    }
+}
+
+impl<'a> TurtleDrawing for SymmetryDrawing<'a> {
+    fn draw_line(&mut self, color: [f32; 4], rot: ShapeRotation, from: [f32; 2], to: [f32; 2], thickness: f32) {
+        let (from, to) = rot.rotate_line(from, to, thickness);
+        let from = self.transform_point(from);
+        let to   = self.transform_point(to);
+        self.inner.draw_line(
+            color, ShapeRotation::LeftBottom(0.0), from, to, thickness);
+    }
+
+    fn draw_rect_fill(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let rot = self.transform_rot(&rot);
+        let pos = self.transform_point(pos);
+        self.inner.draw_rect_fill(color, rot, pos, size);
+    }
+
+    fn draw_rect_outline(&mut self, color: [f32; 4], rot: ShapeRotation, pos: [f32; 2], size: [f32; 2], thickness: f32) {
+        let rot = self.transform_rot(&rot);
+        let pos = self.transform_point(pos);
+        self.inner.draw_rect_outline(color, rot, pos, size, thickness);
+    }
+
+    fn push_clip(&mut self, rot: ShapeRotation, pos: [f32; 2], size: [f32; 2]) {
+        let rot = self.transform_rot(&rot);
+        let pos = self.transform_point(pos);
+        self.inner.push_clip(rot, pos, size);
+    }
+
+    fn pop_clip(&mut self) {
+        self.inner.pop_clip();
+    }
+
+    fn set_layer(&mut self, layer: i64) -> i64 {
+        self.inner.set_layer(layer)
+    }
 }
 
 impl Turtle {
@@ -148,12 +354,97 @@ impl Turtle {
                 let mut sub_ts = ts.clone();
                 cmds.exec(&mut sub_ts, regs, ctx);
             },
+            Turtle::Clip(rw, rh, cmds) => {
+                let w = rw.calc(regs) * ts.w;
+                let h = rh.calc(regs) * ts.h;
+                let angle = ts.get_direction_angle();
+
+                ctx.push_clip(
+                    ShapeRotation::from_anchor(ts.anchor, angle),
+                    [ts.pos[0], ts.pos[1]],
+                    [w, h]);
+                cmds.exec(ts, regs, ctx);
+                ctx.pop_clip();
+            },
+            Turtle::Symmetry(n, mirror, idx_reg, cmds) => {
+                let n = n.calc(regs).round().max(0.0) as usize;
+                let copies = if *mirror { n * 2 } else { n };
+                let axis   = ts.dir[1].atan2(ts.dir[0]);
+
+                for i in 0..copies {
+                    let rot_idx = if *mirror { i / 2 } else { i };
+                    let mut sym = SymmetryDrawing {
+                        inner:  &mut *ctx,
+                        pivot:  ts.pos,
+                        angle:  rot_idx as f32 * 2.0 * std::f32::consts::PI
+                                / n as f32,
+                        mirror: if *mirror && i % 2 == 1 { Some(axis) } else { None },
+                    };
+
+                    if *idx_reg < regs.len() {
+                        regs[*idx_reg] = i as f32;
+                    }
+
+                    let mut sub_ts = ts.clone();
+                    cmds.exec(&mut sub_ts, regs, &mut sym);
+                }
+            },
+            Turtle::Instances(count, base_reg, cmds) => {
+                let count = count.calc(regs).round().max(0.0) as usize;
+
+                let mut inst_regs = vec![0.0; regs.len().max(base_reg + 3)];
+
+                for id in 0..count {
+                    // each instance starts from the same copy of the registers
+                    inst_regs[..regs.len()].copy_from_slice(regs);
+                    for r in inst_regs[regs.len()..].iter_mut() {
+                        *r = 0.0;
+                    }
+
+                    let mut rng = [
+                        (id as u64).wrapping_add(0x193a6754a8a7d469),
+                        (id as u64).wrapping_mul(7).wrapping_add(0x97830e05113ba7bb),
+                    ];
+                    next_xoroshiro128(&mut rng);
+
+                    inst_regs[*base_reg]     = id as f32;
+                    inst_regs[*base_reg + 1] =
+                        if count > 1 { id as f32 / (count - 1) as f32 }
+                        else { 0.0 };
+                    inst_regs[*base_reg + 2] =
+                        u64_to_open01(next_xoroshiro128(&mut rng)) as f32;
+
+                    let mut sub_ts = ts.clone();
+                    cmds.exec(&mut sub_ts, &mut inst_regs, ctx);
+                }
+            },
+            Turtle::Layer(layer, cmds) => {
+                let layer = layer.calc(regs).round() as i64;
+                let prev_layer = ctx.set_layer(layer);
+                cmds.exec(ts, regs, ctx);
+                ctx.set_layer(prev_layer);
+            },
             Turtle::LookDir(x, y) => {
                 let x = x.calc(regs);
                 let y = y.calc(regs);
                 ts.dir = [x as f32, y as f32];
                 ts.dir = vecmath::vec2_normalized(ts.dir);
             },
+            Turtle::Anchor(x, y) => {
+                ts.anchor = [x.calc(regs) as f32, y.calc(regs) as f32];
+            },
+            Turtle::StoreState(reg_x, reg_y, reg_angle) => {
+                let vals = [
+                    (*reg_x,     ts.pos[0] / ts.w),
+                    (*reg_y,     ts.pos[1] / ts.h),
+                    (*reg_angle, ts.get_heading()),
+                ];
+                for (reg, v) in vals.iter() {
+                    if *reg < regs.len() {
+                        regs[*reg] = *v;
+                    }
+                }
+            },
             Turtle::Line(n, thick, color) => {
                 let n     = n.calc(regs);
                 let t     = thick.calc(regs);
@@ -175,7 +466,7 @@ impl Turtle {
 
                 ctx.draw_rect_outline(
                     c,
-                    ShapeRotation::Center(angle),
+                    ShapeRotation::from_anchor(ts.anchor, angle),
                     [ts.pos[0], ts.pos[1]],
                     [w, h],
                     t);
@@ -188,7 +479,7 @@ impl Turtle {
 
                 ctx.draw_rect_fill(
                     c,
-                    ShapeRotation::Center(angle),
+                    ShapeRotation::from_anchor(ts.anchor, angle),
                     [ts.pos[0], ts.pos[1]],
                     [w, h]);
             },
@@ -0,0 +1,4 @@
+/// Clamps `v` to the range 0.0 to 1.0.
+pub fn clamp01(v: f32) -> f32 {
+    if v < 0.0 { 0.0 } else if v > 1.0 { 1.0 } else { v }
+}
@@ -0,0 +1,78 @@
+use crate::turtle::{TurtleDrawing, ShapeRotation};
+
+/// How the virtual canvas is mapped to the output size.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScaleMode {
+    /// uniform scale, the whole canvas is visible and drawing
+    /// outside of it stays visible too.
+    Fit,
+    /// like `Fit`, but everything outside of the canvas is clipped.
+    Letterbox,
+    /// uniform scale, the canvas covers the whole output and
+    /// is cropped at the sides.
+    Fill,
+    /// non uniform scale, the canvas is distorted to the output size.
+    Stretch,
+}
+
+impl ScaleMode {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "fit"       => Some(ScaleMode::Fit),
+            "letterbox" => Some(ScaleMode::Letterbox),
+            "fill"      => Some(ScaleMode::Fill),
+            "stretch"   => Some(ScaleMode::Stretch),
+            _           => None,
+        }
+    }
+}
+
+/// The virtual canvas the demo is composed on, in turtle units.
+/// The origin is in the center of the canvas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viewport {
+    pub width:  f32,
+    pub height: f32,
+    pub mode:   ScaleMode,
+}
+
+impl Viewport {
+    /// The default canvas maps 1 turtle unit to 300 pixels
+    /// in a 640x480 window.
+    pub fn new() -> Self {
+        Viewport {
+            width:  640.0 / 300.0,
+            height: 480.0 / 300.0,
+            mode:   ScaleMode::Fit,
+        }
+    }
+
+    /// Returns the size of one turtle unit in pixels for the
+    /// output size `out_w` x `out_h`.
+    pub fn unit_size(&self, out_w: f32, out_h: f32) -> [f32; 2] {
+        let sx = out_w / self.width;
+        let sy = out_h / self.height;
+
+        match self.mode {
+            ScaleMode::Fit | ScaleMode::Letterbox => [sx.min(sy), sx.min(sy)],
+            ScaleMode::Fill                       => [sx.max(sy), sx.max(sy)],
+            ScaleMode::Stretch                    => [sx, sy],
+        }
+    }
+
+    /// Restricts the drawing to the canvas if the mode
+    /// is `ScaleMode::Letterbox`. Returns true if a clip region was
+    /// pushed, which has to be popped after drawing the frame.
+    pub fn push_letterbox<T>(&self, out_w: f32, out_h: f32, p: &mut T) -> bool
+        where T: TurtleDrawing {
+
+        if self.mode != ScaleMode::Letterbox { return false; }
+
+        let unit = self.unit_size(out_w, out_h);
+        p.push_clip(
+            ShapeRotation::Center(0.0),
+            [0.0, 0.0],
+            [self.width * unit[0], self.height * unit[1]]);
+        true
+    }
+}